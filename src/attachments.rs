@@ -0,0 +1,196 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, bail};
+use futures_util::StreamExt;
+use log::{info, warn};
+use reqwest::Client as HttpClient;
+use sha2::{Digest, Sha256};
+use symphonia::core::{
+    formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+use tokio::io::AsyncWriteExt;
+
+/// Uploaded files, kept by content hash so a track can be replayed after its
+/// Discord CDN link has expired. Least recently used files are dropped once
+/// the cache grows past its limit.
+pub struct AttachmentCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl AttachmentCache {
+    /// `ATTACHMENT_CACHE_MB` limits the size, 1024 by default.
+    pub fn from_env() -> Self {
+        let max_mb: u64 = std::env::var("ATTACHMENT_CACHE_MB")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1024);
+        let dir = crate::cache::dir().join("attachments");
+        Self {
+            // Played through `file://` URLs, which need absolute paths.
+            dir: std::path::absolute(&dir).unwrap_or(dir),
+            max_bytes: max_mb * 1024 * 1024,
+        }
+    }
+
+    /// Downloads `url` unless the same file is already cached, and returns
+    /// where it's stored. Fails for anything that isn't audio.
+    pub async fn store(
+        &self,
+        http: &HttpClient,
+        url: &str,
+        filename: &str,
+        size: u64,
+    ) -> anyhow::Result<PathBuf> {
+        if size > self.max_bytes {
+            bail!("the file is larger than the attachment cache");
+        }
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        let partial = self.dir.join(format!(".{}.part", partial_name(url)));
+        let hash = match download(http, url, &partial).await {
+            Ok(hash) => hash,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return Err(e);
+            }
+        };
+
+        let extension = Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        let probe_path = partial.clone();
+        let probe_extension = extension.clone();
+        let probed =
+            tokio::task::spawn_blocking(move || probe(&probe_path, probe_extension.as_deref()))
+                .await?;
+        if let Err(e) = probed {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e.context(format!("{filename} is not an audio file")));
+        }
+
+        let name = match extension {
+            Some(ext) => format!("{hash}.{ext}"),
+            None => hash,
+        };
+        let path = self.dir.join(name);
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            let _ = tokio::fs::remove_file(&partial).await;
+            touch(&path);
+        } else {
+            tokio::fs::rename(&partial, &path).await?;
+        }
+
+        let dir = self.dir.clone();
+        let max_bytes = self.max_bytes;
+        let keep = path.clone();
+        tokio::task::spawn_blocking(move || evict(&dir, max_bytes, &keep));
+        Ok(path)
+    }
+
+    /// Whether `path` is a file in the cache, symlinks and `..` resolved.
+    pub fn contains(&self, path: &Path) -> bool {
+        let (Ok(path), Ok(dir)) = (path.canonicalize(), self.dir.canonicalize()) else {
+            return false;
+        };
+        path.starts_with(dir) && path.is_file()
+    }
+
+    /// Marks a cached file as used, so it's evicted last. Paths outside the
+    /// cache are left alone.
+    pub fn used(&self, path: &Path) {
+        if path.starts_with(&self.dir) {
+            touch(path);
+        }
+    }
+}
+
+/// A name for the download in progress, unique enough per URL.
+fn partial_name(url: &str) -> String {
+    hex::encode(&Sha256::digest(url)[..8])
+}
+
+/// Streams `url` to `path`, returning the hex SHA-256 of the contents.
+async fn download(http: &HttpClient, url: &str, path: &Path) -> anyhow::Result<String> {
+    let response = http.get(url).send().await?.error_for_status()?;
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut hasher = Sha256::new();
+    let mut body = response.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Checks that symphonia finds an audio track in the file.
+fn probe(path: &Path, extension: Option<&str>) -> anyhow::Result<()> {
+    let mut hint = Hint::new();
+    if let Some(ext) = extension {
+        hint.with_extension(ext);
+    }
+    let mss = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    probed
+        .format
+        .default_track()
+        .filter(|t| t.codec_params.sample_rate.is_some())
+        .context("no audio track")?;
+    Ok(())
+}
+
+fn touch(path: &Path) {
+    if let Err(e) = File::options()
+        .append(true)
+        .open(path)
+        .and_then(|f| f.set_modified(SystemTime::now()))
+    {
+        warn!("could not touch {}: {e}", path.display());
+    }
+}
+
+/// Deletes the least recently used files until the cache fits in
+/// `max_bytes`, never deleting `keep`.
+fn evict(dir: &Path, max_bytes: u64, keep: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<(PathBuf, u64, SystemTime)> = entries
+        .filter_map(Result::ok)
+        .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            Some((e.path(), meta.len(), meta.modified().ok()?))
+        })
+        .collect();
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    files.sort_by_key(|(_, _, modified)| *modified);
+
+    for (path, len, _) in files {
+        if total <= max_bytes {
+            break;
+        }
+        if path == keep {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                info!("Evicted {} from the attachment cache", path.display());
+                total -= len;
+            }
+            Err(e) => warn!("could not evict {}: {e}", path.display()),
+        }
+    }
+}
@@ -0,0 +1,634 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use futures_util::StreamExt;
+use log::{error, info, warn};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use reqwest::Client as HttpClient;
+use serenity::all::GuildId;
+use serenity::prelude::*;
+use songbird::{
+    input::{
+        Input, LiveInput,
+        codecs::{get_codec_registry, get_probe},
+    },
+    tracks::{PlayMode, TrackHandle},
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    formats::{SeekMode, SeekTo},
+    units::Time,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+};
+use url::Url;
+
+use crate::{
+    UserData,
+    commands::play::TrackMeta,
+    pcm::{CHANNELS, FRAME_SAMPLES, PcmBuffer, SAMPLE_RATE},
+};
+
+const FRAME: Duration = Duration::from_millis(20);
+/// How far the playback decoder runs ahead of the encoder.
+const DECODE_AHEAD: usize = FRAME_SAMPLES * 10;
+/// Re-seek the playback decoder when it drifts further than this from the track.
+const MAX_DRIFT: Duration = Duration::from_secs(2);
+const ICECAST_RETRY: Duration = Duration::from_secs(5);
+
+/// Running broadcasts, shared between the bot and the HTTP server.
+pub type Broadcasts = Arc<Mutex<HashMap<GuildId, Arc<Broadcast>>>>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// What the bot is playing.
+    Playback,
+    /// Everyone talking in the voice channel.
+    Voice,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Opus,
+    Mp3,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Opus => "audio/ogg",
+            Format::Mp3 => "audio/mpeg",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Opus => "ogg",
+            Format::Mp3 => "mp3",
+        }
+    }
+}
+
+/// One guild's outgoing stream.
+pub struct Broadcast {
+    pub source: Source,
+    pub format: Format,
+    /// The audio fed to the encoder. For [`Source::Voice`] this is pushed to
+    /// from a `VoiceTick` handler.
+    pub pcm: PcmBuffer,
+    active: Arc<AtomicBool>,
+    /// Ogg streams can't be joined without their header pages, so every new
+    /// listener gets these first.
+    headers: Vec<u8>,
+    tx: broadcast::Sender<Arc<Vec<u8>>>,
+    /// Mount being pushed to, with credentials.
+    icecast: Option<Url>,
+}
+
+impl Broadcast {
+    /// Starts encoding and, if `ICECAST_URL` is set, pushing to Icecast.
+    /// `ICECAST_URL` may contain `{guild}` to give each guild its own mount.
+    pub fn start(
+        ctx: &Context,
+        http: HttpClient,
+        guild_id: GuildId,
+        source: Source,
+        format: Format,
+    ) -> anyhow::Result<Arc<Self>> {
+        let mut encoder: Box<dyn Encode> = match format {
+            Format::Opus => Box::new(OggOpus::new()?),
+            Format::Mp3 => Box::new(Mp3::new()?),
+        };
+        let icecast = std::env::var("ICECAST_URL").ok().and_then(|u| {
+            Url::parse(&u.replace("{guild}", &guild_id.to_string()))
+                .map_err(|e| warn!("Ignoring invalid ICECAST_URL: {e}"))
+                .ok()
+        });
+        let (tx, _) = broadcast::channel(64);
+        let broadcast = Arc::new(Self {
+            source,
+            format,
+            pcm: PcmBuffer::default(),
+            active: Arc::new(AtomicBool::new(true)),
+            headers: encoder.headers(),
+            tx,
+            icecast,
+        });
+
+        let b = broadcast.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FRAME);
+            let mut frame = [0f32; FRAME_SAMPLES];
+            while b.is_active() {
+                interval.tick().await;
+
+                let mut filled = 0;
+                while filled < FRAME_SAMPLES {
+                    filled += b.pcm.read_frame(&mut frame[filled..]);
+                }
+                match encoder.encode(&frame) {
+                    Ok(bytes) if !bytes.is_empty() => {
+                        let _ = b.tx.send(Arc::new(bytes));
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!("Broadcast encoder for {guild_id} failed: {e:?}");
+                        b.stop();
+                    }
+                }
+            }
+        });
+
+        if source == Source::Playback {
+            spawn_playback_decoder(ctx.clone(), guild_id, broadcast.clone());
+        }
+        if let Some(url) = broadcast.icecast.clone() {
+            spawn_icecast(http, url, broadcast.clone());
+        }
+
+        Ok(broadcast)
+    }
+
+    /// Updates the song title Icecast shows to listeners.
+    async fn set_title(&self, http: &HttpClient, title: &str) {
+        let Some(mount) = &self.icecast else {
+            return;
+        };
+        let mut url = mount.clone();
+        url.set_path("/admin/metadata");
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("mount", mount.path())
+            .append_pair("mode", "updinfo")
+            .append_pair("song", title);
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+
+        let user = match mount.username() {
+            "" => "source",
+            u => u,
+        };
+        if let Err(e) = http
+            .get(url)
+            .basic_auth(user, mount.password())
+            .send()
+            .await
+        {
+            warn!("Could not update Icecast metadata: {e}");
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn stop(&self) {
+        self.active.store(false, Ordering::Relaxed);
+    }
+}
+
+trait Encode: Send {
+    /// Bytes every listener needs before the first encoded frame.
+    fn headers(&mut self) -> Vec<u8>;
+    /// Encodes one 20ms frame, returning whatever output is complete.
+    fn encode(&mut self, frame: &[f32]) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Opus in Ogg, one page every [`OggOpus::PACKETS_PER_PAGE`] frames.
+struct OggOpus {
+    encoder: audiopus::coder::Encoder,
+    writer: PacketWriter<'static, Vec<u8>>,
+    pre_skip: u16,
+    granule: u64,
+    packets: u32,
+}
+
+impl OggOpus {
+    const SERIAL: u32 = 0x5342_5244;
+    const PACKETS_PER_PAGE: u32 = 10;
+
+    fn new() -> anyhow::Result<Self> {
+        let mut encoder = audiopus::coder::Encoder::new(
+            audiopus::SampleRate::Hz48000,
+            audiopus::Channels::Stereo,
+            audiopus::Application::Audio,
+        )?;
+        encoder.set_bitrate(audiopus::Bitrate::BitsPerSecond(128_000))?;
+        let pre_skip = encoder.lookahead()? as u16;
+
+        Ok(Self {
+            encoder,
+            writer: PacketWriter::new(vec![]),
+            pre_skip,
+            granule: pre_skip as u64,
+            packets: 0,
+        })
+    }
+}
+
+impl Encode for OggOpus {
+    fn headers(&mut self) -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(CHANNELS as u8);
+        head.extend(self.pre_skip.to_le_bytes());
+        head.extend(SAMPLE_RATE.to_le_bytes());
+        head.extend(0i16.to_le_bytes());
+        head.push(0);
+
+        let vendor = b"audio-bot";
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend((vendor.len() as u32).to_le_bytes());
+        tags.extend(vendor);
+        tags.extend(0u32.to_le_bytes());
+
+        // Both headers have to sit on pages of their own.
+        for packet in [head, tags] {
+            self.writer
+                .write_packet(packet, Self::SERIAL, PacketWriteEndInfo::EndPage, 0)
+                .expect("writing to a Vec can't fail");
+        }
+        std::mem::take(self.writer.inner_mut())
+    }
+
+    fn encode(&mut self, frame: &[f32]) -> anyhow::Result<Vec<u8>> {
+        let mut packet = [0u8; 4000];
+        let len = self.encoder.encode_float(frame, &mut packet)?;
+
+        self.granule += (frame.len() / CHANNELS as usize) as u64;
+        self.packets += 1;
+        let end = if self.packets.is_multiple_of(Self::PACKETS_PER_PAGE) {
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        self.writer
+            .write_packet(packet[..len].to_vec(), Self::SERIAL, end, self.granule)?;
+
+        Ok(std::mem::take(self.writer.inner_mut()))
+    }
+}
+
+struct Mp3 {
+    encoder: mp3lame_encoder::Encoder,
+}
+
+impl Mp3 {
+    fn new() -> anyhow::Result<Self> {
+        let mut builder = mp3lame_encoder::Builder::new().context("could not create LAME")?;
+        builder
+            .set_num_channels(CHANNELS as u8)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        builder
+            .set_sample_rate(SAMPLE_RATE)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        builder
+            .set_brate(mp3lame_encoder::Bitrate::Kbps192)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        let encoder = builder.build().map_err(|e| anyhow::anyhow!("{e}"))?;
+
+        Ok(Self { encoder })
+    }
+}
+
+impl Encode for Mp3 {
+    fn headers(&mut self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn encode(&mut self, frame: &[f32]) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(
+            frame.len() / CHANNELS as usize,
+        ));
+        self.encoder
+            .encode_to_vec(mp3lame_encoder::InterleavedPcm(frame), &mut out)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        Ok(out)
+    }
+}
+
+/// Pushes the stream to an Icecast/SHOUTcast mount with an HTTP `PUT`,
+/// reconnecting until the broadcast stops.
+fn spawn_icecast(http: HttpClient, mut url: Url, broadcast: Arc<Broadcast>) {
+    let user = match url.username() {
+        "" => "source".to_string(),
+        u => u.to_string(),
+    };
+    let password = url.password().map(str::to_string);
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+
+    tokio::spawn(async move {
+        while broadcast.is_active() {
+            let rx = broadcast.tx.subscribe();
+            let headers = broadcast.headers.clone();
+            let b = broadcast.clone();
+            let body = futures_util::stream::once(async move { Ok(headers) }).chain(
+                futures_util::stream::unfold(rx, move |mut rx| {
+                    let b = b.clone();
+                    async move {
+                        loop {
+                            if !b.is_active() {
+                                return None;
+                            }
+                            match rx.recv().await {
+                                Ok(bytes) => {
+                                    return Some((Ok::<_, std::io::Error>(bytes.to_vec()), rx));
+                                }
+                                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(broadcast::error::RecvError::Closed) => return None,
+                            }
+                        }
+                    }
+                }),
+            );
+
+            info!("Connecting to Icecast at {url}");
+            let result = http
+                .put(url.clone())
+                .basic_auth(&user, password.as_ref())
+                .header("content-type", broadcast.format.content_type())
+                .header("ice-name", "audio-bot")
+                .header("ice-public", "0")
+                .body(reqwest::Body::wrap_stream(body))
+                .send()
+                .await;
+            match result {
+                Ok(r) if r.status().is_success() => {}
+                Ok(r) => warn!("Icecast rejected the stream: {}", r.status()),
+                Err(e) => warn!("Icecast connection dropped: {e}"),
+            }
+
+            if broadcast.is_active() {
+                tokio::time::sleep(ICECAST_RETRY).await;
+            }
+        }
+    });
+}
+
+/// Serves every running broadcast at `http://<addr>/<guild id>`.
+pub async fn serve(addr: String, broadcasts: Broadcasts) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    info!("Serving broadcasts on http://{addr}/<guild id>");
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let broadcasts = broadcasts.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_listener(stream, broadcasts).await {
+                info!("Broadcast listener disconnected: {e}");
+            }
+        });
+    }
+}
+
+async fn serve_listener(mut stream: TcpStream, broadcasts: Broadcasts) -> anyhow::Result<()> {
+    let mut request = vec![];
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() > 8192 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    // "GET /1234567890.ogg HTTP/1.1"
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let broadcast = path
+        .trim_start_matches('/')
+        .split('.')
+        .next()
+        .and_then(|id| id.parse().ok())
+        .and_then(|id| broadcasts.lock().unwrap().get(&GuildId::new(id)).cloned());
+
+    let Some(broadcast) = broadcast else {
+        stream
+            .write_all(b"HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+            .await?;
+        return Ok(());
+    };
+
+    let mut rx = broadcast.tx.subscribe();
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.0 200 OK\r\nContent-Type: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+                broadcast.format.content_type()
+            )
+            .as_bytes(),
+        )
+        .await?;
+    stream.write_all(&broadcast.headers).await?;
+
+    while broadcast.is_active() {
+        match rx.recv().await {
+            Ok(bytes) => stream.write_all(&bytes).await?,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+    Ok(())
+}
+
+/// Songbird doesn't expose its mixed output, so for [`Source::Playback`] a
+/// second decoder follows the guild's current track and keeps it in step
+/// with the track's position, play state and volume.
+fn spawn_playback_decoder(ctx: Context, guild_id: GuildId, broadcast: Arc<Broadcast>) {
+    tokio::spawn(async move {
+        let mut current: Option<(TrackHandle, Decoder)> = None;
+
+        while broadcast.is_active() {
+            let (track, http) = {
+                let typemap = ctx.data.read().await;
+                let data = typemap.get::<UserData>().unwrap();
+                (
+                    data.track_handles.get(&guild_id).cloned(),
+                    data.http.clone(),
+                )
+            };
+            let info = match &track {
+                Some(t) => t.get_info().await.ok(),
+                None => None,
+            };
+            let (Some(track), Some(info)) = (track, info) else {
+                current = None;
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                continue;
+            };
+
+            let same_track = current
+                .as_ref()
+                .is_some_and(|(t, _)| t.uuid() == track.uuid());
+            if !same_track {
+                let meta = track.data::<TrackMeta>();
+                broadcast.set_title(&http, &meta.title).await;
+                match Decoder::open(http, &meta.url, info.position).await {
+                    Ok(d) => current = Some((track.clone(), d)),
+                    Err(e) => {
+                        warn!("Could not decode {} for broadcast: {e:?}", meta.url);
+                        current = None;
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                }
+            }
+
+            if info.playing != PlayMode::Play || broadcast.pcm.len() >= DECODE_AHEAD {
+                tokio::time::sleep(FRAME).await;
+                continue;
+            }
+
+            let (handle, decoder) = current.take().unwrap();
+            let pcm = broadcast.pcm.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let mut decoder = decoder;
+                let result = decoder.fill(&pcm, info.position, info.volume);
+                (decoder, result)
+            })
+            .await;
+            match result {
+                Ok((decoder, Ok(()))) => current = Some((handle, decoder)),
+                // Finished: wait for the track to loop or change.
+                Ok((_, Err(e))) => {
+                    info!("Broadcast decoder for {guild_id} stopped: {e}");
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                }
+                Err(e) => error!("Broadcast decoder panicked: {e}"),
+            }
+        }
+    });
+}
+
+/// A private copy of a track, decoded to 48kHz stereo.
+struct Decoder {
+    parsed: songbird::input::Parsed,
+    /// Position of the next decoded sample.
+    position: Duration,
+    live: bool,
+}
+
+impl Decoder {
+    async fn open(http: HttpClient, url: &Url, position: Duration) -> anyhow::Result<Self> {
+        let playable = crate::source::resolve(&http, url).await;
+        let live = playable.live.is_some();
+        let input = playable
+            .input
+            .make_playable_async(get_codec_registry(), get_probe())
+            .await?;
+        let Input::Live(LiveInput::Parsed(parsed), _) = input else {
+            anyhow::bail!("input was not parsed");
+        };
+
+        let mut decoder = Self {
+            parsed,
+            position: Duration::ZERO,
+            live,
+        };
+        // A live stream is always at "now", wherever the track thinks it is.
+        if !live {
+            decoder.seek(position)?;
+        }
+        Ok(decoder)
+    }
+
+    fn seek(&mut self, position: Duration) -> anyhow::Result<()> {
+        let seeked = self.parsed.format.seek(
+            SeekMode::Coarse,
+            SeekTo::Time {
+                time: Time::from(position.as_secs_f64()),
+                track_id: Some(self.parsed.track_id),
+            },
+        )?;
+        self.parsed.decoder.reset();
+
+        let rate = self
+            .parsed
+            .decoder
+            .codec_params()
+            .sample_rate
+            .unwrap_or(SAMPLE_RATE);
+        self.position = Duration::from_secs_f64(seeked.actual_ts as f64 / rate as f64);
+        Ok(())
+    }
+
+    /// Decodes until `pcm` holds [`DECODE_AHEAD`] samples, first seeking if
+    /// the track has moved away from where this decoder is.
+    fn fill(
+        &mut self,
+        pcm: &PcmBuffer,
+        track_position: Duration,
+        volume: f32,
+    ) -> anyhow::Result<()> {
+        let buffered = Duration::from_secs_f64(pcm.len() as f64 / (SAMPLE_RATE * CHANNELS) as f64);
+        let expected = track_position + buffered;
+        if !self.live && self.position.abs_diff(expected) > MAX_DRIFT {
+            self.seek(track_position)?;
+        }
+
+        while pcm.len() < DECODE_AHEAD {
+            let packet = self.parsed.format.next_packet()?;
+            if packet.track_id() != self.parsed.track_id {
+                continue;
+            }
+            let decoded = self.parsed.decoder.decode(&packet)?;
+            let spec = *decoded.spec();
+            let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            samples.copy_interleaved_ref(decoded);
+
+            let stereo = to_stereo(samples.samples(), spec.channels.count());
+            let stereo = resample(&stereo, spec.rate);
+            self.position += Duration::from_secs_f64(
+                (stereo.len() / CHANNELS as usize) as f64 / SAMPLE_RATE as f64,
+            );
+            pcm.push_f32(&stereo, volume);
+        }
+        Ok(())
+    }
+}
+
+fn to_stereo(samples: &[f32], channels: usize) -> Vec<f32> {
+    match channels {
+        2 => samples.to_vec(),
+        1 => samples.iter().flat_map(|s| [*s, *s]).collect(),
+        n => samples
+            .chunks_exact(n)
+            .flat_map(|frame| [frame[0], frame[1]])
+            .collect(),
+    }
+}
+
+/// Linear resampling of interleaved stereo to 48kHz.
+fn resample(samples: &[f32], rate: u32) -> Vec<f32> {
+    if rate == SAMPLE_RATE {
+        return samples.to_vec();
+    }
+
+    let frames = samples.len() / 2;
+    let out_frames = frames * SAMPLE_RATE as usize / rate as usize;
+    let step = rate as f64 / SAMPLE_RATE as f64;
+    let mut out = Vec::with_capacity(out_frames * 2);
+    for i in 0..out_frames {
+        let pos = i as f64 * step;
+        let idx = pos as usize;
+        let frac = (pos - idx as f64) as f32;
+        let next = (idx + 1).min(frames - 1);
+        for c in 0..2 {
+            let a = samples[idx * 2 + c];
+            let b = samples[next * 2 + c];
+            out.push(a + (b - a) * frac);
+        }
+    }
+    out
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+
+/// Entries kept in memory before the oldest are dropped. They stay on disk.
+const MEMORY_LEN: usize = 10_000;
+/// How often files past their `max_age` are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Where caches and other state the bot can rebuild live, `CACHE_DIR` or
+/// `./cache`.
+pub fn dir() -> PathBuf {
+    std::env::var("CACHE_DIR")
+        .unwrap_or_else(|_| "cache".to_string())
+        .into()
+}
+
+/// JSON values kept in memory and mirrored to one file per key, so they
+/// survive restarts. Expiry is decided by the reader, see [`Cache::get`],
+/// but files nobody could want anymore are deleted once a day.
+pub struct Cache {
+    dir: PathBuf,
+    memory: Mutex<HashMap<String, Entry>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    /// Unix seconds.
+    stored: u64,
+    value: serde_json::Value,
+}
+
+impl Cache {
+    /// `max_age` should be at least the longest `ttl` anyone reads with.
+    pub fn new(dir: PathBuf, max_age: Duration) -> Self {
+        let prune_dir = dir.clone();
+        tokio::spawn(async move {
+            loop {
+                let dir = prune_dir.clone();
+                let _ = tokio::task::spawn_blocking(move || prune(&dir, max_age)).await;
+                tokio::time::sleep(PRUNE_INTERVAL).await;
+            }
+        });
+        Self {
+            dir,
+            memory: Mutex::default(),
+        }
+    }
+
+    /// The value stored under `key`, unless it is older than `ttl`.
+    pub async fn get<T: DeserializeOwned>(&self, key: &str, ttl: Duration) -> Option<T> {
+        let cached = self.memory.lock().unwrap().get(key).cloned();
+        let entry = match cached {
+            Some(entry) => entry,
+            None => {
+                let bytes = tokio::fs::read(self.path(key)).await.ok()?;
+                let entry: Entry = serde_json::from_slice(&bytes).ok()?;
+                self.remember(key, entry.clone());
+                entry
+            }
+        };
+
+        if now().saturating_sub(entry.stored) > ttl.as_secs() {
+            return None;
+        }
+        serde_json::from_value(entry.value).ok()
+    }
+
+    pub async fn insert<T: Serialize>(&self, key: &str, value: &T) {
+        let Ok(value) = serde_json::to_value(value) else {
+            return;
+        };
+        let entry = Entry {
+            stored: now(),
+            value,
+        };
+
+        let path = self.path(key);
+        let write = async {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(&path, serde_json::to_vec(&entry)?).await
+        };
+        if let Err(e) = write.await {
+            warn!("could not write cache entry {}: {e}", path.display());
+        }
+        self.remember(key, entry);
+    }
+
+    fn remember(&self, key: &str, entry: Entry) {
+        let mut memory = self.memory.lock().unwrap();
+        if memory.len() >= MEMORY_LEN
+            && let Some(oldest) = memory
+                .iter()
+                .min_by_key(|(_, e)| e.stored)
+                .map(|(k, _)| k.clone())
+        {
+            memory.remove(&oldest);
+        }
+        memory.insert(key.to_string(), entry);
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}.json", hex::encode(Sha256::digest(key))))
+    }
+}
+
+/// Deletes entry files in `dir` last written more than `max_age` ago. Other
+/// files, like the quota kept next to the YouTube cache, are left alone.
+fn prune(dir: &Path, max_age: Duration) {
+    let Ok(files) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut pruned = 0;
+    for file in files.flatten() {
+        let path = file.path();
+        let is_entry = path.extension().is_some_and(|e| e == "json")
+            && path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .is_some_and(|s| s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit()));
+        let expired = file
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.elapsed().ok())
+            .is_some_and(|age| age > max_age);
+        if is_entry && expired && std::fs::remove_file(&path).is_ok() {
+            pruned += 1;
+        }
+    }
+    debug!("pruned {pruned} cache entries from {}", dir.display());
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{UserData, search::SearchResult};
+
+/// Discord shows at most this many choices.
+const MAX_CHOICES: usize = 25;
+/// Suggestions come from [`crate::search::SearchProvider::suggest`], which
+/// leaves out the YouTube Data API since its searches are too dear to spend on
+/// keystrokes. Each user still gets at most one lookup every so often and
+/// results are reused for a while.
+const USER_INTERVAL: Duration = Duration::from_secs(2);
+const CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+const CACHE_LEN: usize = 500;
+/// Discord drops autocomplete responses that take longer than 3 seconds.
+/// Slower lookups still finish and are cached for the next keystroke.
+const PROVIDER_TIMEOUT: Duration = Duration::from_millis(2000);
+const MIN_QUERY_CHARS: usize = 3;
+const PROVIDER_RESULTS: usize = 10;
+
+/// Search results for autocomplete, shared by all guilds.
+#[derive(Default)]
+pub struct Suggestions {
+    cache: Mutex<HashMap<String, (Instant, Vec<SearchResult>)>>,
+    last_lookup: Mutex<HashMap<UserId, Instant>>,
+}
+
+impl Suggestions {
+    fn cached(&self, query: &str) -> Option<Vec<SearchResult>> {
+        let cache = self.cache.lock().unwrap();
+        let (at, results) = cache.get(query)?;
+        (at.elapsed() < CACHE_TTL).then(|| results.clone())
+    }
+
+    fn insert(&self, query: String, results: Vec<SearchResult>) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (at, _)| at.elapsed() < CACHE_TTL);
+        if cache.len() < CACHE_LEN {
+            cache.insert(query, (Instant::now(), results));
+        }
+    }
+
+    /// Whether `user` may hit the provider now, recording it if so.
+    fn allow(&self, user: UserId) -> bool {
+        let mut last = self.last_lookup.lock().unwrap();
+        last.retain(|_, at| at.elapsed() < USER_INTERVAL);
+        if last.contains_key(&user) {
+            return false;
+        }
+        last.insert(user, Instant::now());
+        true
+    }
+}
+
+/// Suggests tracks for `/play web link` and `/search query`: matching tracks
+/// played here first, then from saved playlists, then search results.
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    if interaction.data.name == "sound" {
+        return super::sound::autocomplete(ctx, interaction).await;
+    }
+    if interaction.data.name == "playlist" {
+        return super::playlist::autocomplete(ctx, interaction).await;
+    }
+    let Some(focused) = interaction.data.autocomplete() else {
+        return Ok(());
+    };
+    // `/play` wants something it can play, `/search` wants text to search.
+    let as_link = interaction.data.name == "play";
+    let typed = focused.value.trim();
+    let needle = typed.to_lowercase();
+
+    let (recent, saved, history, search, suggestions) = {
+        let typemap = ctx.data.read().await;
+        let data = typemap.get::<UserData>().unwrap();
+        let recent: Vec<(String, String)> = interaction
+            .guild_id
+            .and_then(|g| data.recent.get(&g))
+            .into_iter()
+            .flatten()
+            .filter(|e| e.title.to_lowercase().contains(&needle))
+            .map(|e| (e.title.clone(), e.url.to_string()))
+            .collect();
+        let saved: Vec<(String, String)> = data
+            .playlists
+            .tracks_matching(interaction.user.id, interaction.guild_id, &needle)
+            .into_iter()
+            .map(|t| (t.title, t.url))
+            .collect();
+        (
+            recent,
+            saved,
+            data.history.clone(),
+            data.search.clone(),
+            data.suggestions.clone(),
+        )
+    };
+
+    let mut choices = recent;
+    // Plays from before the last restart too, read off the runtime.
+    if let Some(guild_id) = interaction.guild_id {
+        let needle = needle.clone();
+        let plays =
+            tokio::task::spawn_blocking(move || history.matching(guild_id, &needle, MAX_CHOICES))
+                .await
+                .unwrap_or_default();
+        choices.extend(plays.into_iter().map(|p| (p.title, p.url)));
+    }
+    choices.extend(saved);
+
+    // Links are played as they are, there's nothing to look up.
+    if typed.chars().count() >= MIN_QUERY_CHARS && url::Url::parse(typed).is_err() {
+        let results = match suggestions.cached(&needle) {
+            Some(results) => results,
+            None if suggestions.allow(interaction.user.id) => {
+                let lookup = tokio::spawn({
+                    let (typed, suggestions) = (typed.to_string(), suggestions.clone());
+                    async move {
+                        match search.suggest(&typed, PROVIDER_RESULTS).await {
+                            Ok(results) => {
+                                suggestions.insert(needle, results.clone());
+                                results
+                            }
+                            Err(e) => {
+                                warn!("autocomplete search failed: {e:?}");
+                                vec![]
+                            }
+                        }
+                    }
+                });
+                match tokio::time::timeout(PROVIDER_TIMEOUT, lookup).await {
+                    Ok(Ok(results)) => results,
+                    _ => vec![],
+                }
+            }
+            None => vec![],
+        };
+        choices.extend(results.into_iter().map(|r| {
+            let url = format!("https://youtube.com/watch?v={}", r.id);
+            (r.title, url)
+        }));
+    }
+
+    let mut response = CreateAutocompleteResponse::new();
+    let mut seen = vec![];
+    for (title, url) in choices {
+        if seen.len() == MAX_CHOICES {
+            break;
+        }
+        // Values can't be cut short like names, long links are left out.
+        if seen.contains(&url) || (as_link && url.chars().count() > 100) {
+            continue;
+        }
+        let name = truncate(&title, 100);
+        let value = if as_link { &url } else { truncate(&title, 100) };
+        response = response.add_string_choice(name, value);
+        seen.push(url);
+    }
+
+    interaction
+        .create_response(ctx, CreateInteractionResponse::Autocomplete(response))
+        .await
+}
+
+fn truncate(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        None => s,
+        Some((idx, _)) => &s[..idx],
+    }
+}
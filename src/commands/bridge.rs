@@ -0,0 +1,354 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use log::warn;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use serenity::{async_trait, builder::*};
+use songbird::{CoreEvent, Event, EventContext, EventHandler, tracks::TrackHandle};
+
+use crate::{
+    COLOR_ERROR, COLOR_OK, UserData,
+    pcm::{self, PcmBuffer},
+};
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("bridge")
+        .description("Relay voice between your channel and a voice channel in another server")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "start",
+                "Bridge your voice channel to another one",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "channel",
+                    "ID or mention of the voice channel to bridge to",
+                )
+                .required(true),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "volume",
+                "Adjust the volume of one side of the bridge",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "direction",
+                    "Which side to adjust",
+                )
+                .add_string_choice("Incoming (what we hear from them)", "in")
+                .add_string_choice("Outgoing (what they hear from us)", "out")
+                .required(true),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Number,
+                    "number",
+                    "A number from 0 to 200, default 100",
+                )
+                .min_number_value(0.0)
+                .max_number_value(200.0)
+                .required(true),
+            ),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "stop",
+            "Tear down the bridge",
+        ))
+}
+
+/// A two-way voice relay between channels in two different guilds. Discord
+/// only allows one voice connection per guild, so both ends can't live in the
+/// same one.
+pub struct Bridge {
+    active: Arc<AtomicBool>,
+    sides: [Side; 2],
+}
+
+struct Side {
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    /// Whether the call only exists because of the bridge.
+    joined: bool,
+    /// Plays what the other side says.
+    track: TrackHandle,
+    /// Volume of `track`, stored as `f32` bits.
+    volume: Arc<AtomicU32>,
+}
+
+impl Bridge {
+    fn side(&self, guild_id: GuildId) -> &Side {
+        if self.sides[0].guild_id == guild_id {
+            &self.sides[0]
+        } else {
+            &self.sides[1]
+        }
+    }
+
+    fn other_side(&self, guild_id: GuildId) -> &Side {
+        if self.sides[0].guild_id == guild_id {
+            &self.sides[1]
+        } else {
+            &self.sides[0]
+        }
+    }
+}
+
+/// Mixes everyone speaking in one call into the other side's buffer.
+#[derive(Clone)]
+struct BridgeTap {
+    active: Arc<AtomicBool>,
+    out: PcmBuffer,
+    volume: Arc<AtomicU32>,
+}
+
+#[async_trait]
+impl EventHandler for BridgeTap {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if !self.active.load(Ordering::Relaxed) {
+            return Some(Event::Cancel);
+        }
+
+        if let EventContext::VoiceTick(tick) = ctx {
+            let mix = pcm::mix_voice(tick);
+            if !mix.is_empty() {
+                let volume = f32::from_bits(self.volume.load(Ordering::Relaxed));
+                self.out.push_i16(&mix, volume);
+            }
+        }
+
+        None
+    }
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let Some(ResolvedOption {
+        name,
+        value: ResolvedValue::SubCommand(options),
+        ..
+    }) = interaction.data.options().first().cloned()
+    else {
+        warn!("bridge interaction option not subcommand");
+        return Ok(());
+    };
+
+    let embed = match name {
+        "start" => start(ctx, interaction, &options).await,
+        "volume" => volume(ctx, interaction, &options).await,
+        "stop" => {
+            let guild_id = interaction.guild_id.unwrap();
+            let mut typemap = ctx.data.write().await;
+            let data = typemap.get_mut::<UserData>().unwrap();
+            if teardown(data, guild_id).await {
+                Ok(CreateEmbed::new().title("Bridge stopped"))
+            } else {
+                Err("There is no bridge in this server".to_string())
+            }
+        }
+        _ => return Ok(()),
+    };
+
+    let embed = match embed {
+        Ok(embed) => embed.color(Colour::new(COLOR_OK)),
+        Err(e) => CreateEmbed::new()
+            .color(Colour::new(COLOR_ERROR))
+            .description(e)
+            .title("Error"),
+    };
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().embed(embed.timestamp(Timestamp::now())),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn start(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> Result<CreateEmbed, String> {
+    let guild_id = interaction.guild_id.unwrap();
+    let channel_id = ctx
+        .cache
+        .guild(guild_id)
+        .and_then(|g| g.voice_states.get(&interaction.user.id)?.channel_id)
+        .ok_or("Join the voice channel you want to bridge first")?;
+
+    let Some(ResolvedOption {
+        value: ResolvedValue::String(target),
+        ..
+    }) = options.first()
+    else {
+        return Err("Missing channel".into());
+    };
+    let target_channel = serenity::utils::parse_channel_mention(target)
+        .or_else(|| target.parse().ok().map(ChannelId::new))
+        .ok_or("Not a channel ID or mention")?;
+    let target = ctx.cache.guilds().into_iter().find_map(|g| {
+        let kind = ctx.cache.guild(g)?.channels.get(&target_channel)?.kind;
+        Some((g, kind))
+    });
+    let target_guild = match target {
+        Some((g, ChannelType::Voice | ChannelType::Stage)) => g,
+        Some(_) => return Err("That isn't a voice channel".into()),
+        None => return Err("I can't see that channel".into()),
+    };
+    if target_guild == guild_id {
+        return Err(
+            "Both channels are in this server, but Discord only allows one voice connection per server"
+                .into(),
+        );
+    }
+
+    let mut typemap = ctx.data.write().await;
+    let data = typemap.get_mut::<UserData>().unwrap();
+    if data.bridges.contains_key(&guild_id) || data.bridges.contains_key(&target_guild) {
+        return Err("One of these servers is already bridged, use `/bridge stop` first".into());
+    }
+
+    let active = Arc::new(AtomicBool::new(true));
+    let buffers = [PcmBuffer::default(), PcmBuffer::default()];
+    let volumes = [
+        Arc::new(AtomicU32::new(1f32.to_bits())),
+        Arc::new(AtomicU32::new(1f32.to_bits())),
+    ];
+    let mut sides = vec![];
+    for (i, (guild, channel)) in [(guild_id, channel_id), (target_guild, target_channel)]
+        .into_iter()
+        .enumerate()
+    {
+        let joined = data.songbird.get(guild).is_none();
+        let handler_lock = match data.songbird.join(guild, channel).await {
+            Ok(h) => h,
+            Err(e) => {
+                warn!("{}", e);
+                active.store(false, Ordering::Relaxed);
+                for side in &sides {
+                    release(data, side).await;
+                }
+                if joined {
+                    let _ = data.songbird.remove(guild).await;
+                }
+                return Err(format!("Could not join {}", channel.mention()));
+            }
+        };
+
+        let mut handler = handler_lock.lock().await;
+        let _ = handler.deafen(false).await;
+        handler.add_global_event(
+            CoreEvent::VoiceTick.into(),
+            BridgeTap {
+                active: active.clone(),
+                out: buffers[1 - i].clone(),
+                volume: volumes[1 - i].clone(),
+            },
+        );
+        let track = handler.play_input(buffers[i].input());
+        sides.push(Side {
+            guild_id: guild,
+            channel_id: channel,
+            joined,
+            track,
+            volume: volumes[i].clone(),
+        });
+    }
+
+    let bridge = Arc::new(Bridge {
+        active,
+        sides: sides.try_into().ok().unwrap(),
+    });
+    data.bridges.insert(guild_id, bridge.clone());
+    data.bridges.insert(target_guild, bridge);
+
+    Ok(CreateEmbed::new()
+        .title("Bridge started")
+        .description(format!(
+            "{} ⇄ {}",
+            channel_id.mention(),
+            target_channel.mention()
+        )))
+}
+
+async fn volume(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> Result<CreateEmbed, String> {
+    let guild_id = interaction.guild_id.unwrap();
+    let (
+        Some(ResolvedOption {
+            value: ResolvedValue::String(direction),
+            ..
+        }),
+        Some(ResolvedOption {
+            value: ResolvedValue::Number(num),
+            ..
+        }),
+    ) = (options.first(), options.get(1))
+    else {
+        return Err("Could not set volume".into());
+    };
+
+    let typemap = ctx.data.read().await;
+    let data = typemap.get::<UserData>().unwrap();
+    let bridge = data
+        .bridges
+        .get(&guild_id)
+        .ok_or("There is no bridge in this server")?;
+
+    // Each side's volume applies to what gets played into it.
+    let side = match *direction {
+        "in" => bridge.side(guild_id),
+        _ => bridge.other_side(guild_id),
+    };
+    let volume = (*num / 100.0) as f32;
+    side.volume.store(volume.to_bits(), Ordering::Relaxed);
+
+    Ok(CreateEmbed::new()
+        .title("Bridge volume")
+        .description(format!(
+            "Set {} volume in {} to {}",
+            if *direction == "in" {
+                "incoming"
+            } else {
+                "outgoing"
+            },
+            side.channel_id.mention(),
+            num
+        )))
+}
+
+/// Stops the bridge `guild_id` is part of, leaving any call that was only
+/// joined for it. Returns whether there was one.
+pub async fn teardown(data: &mut UserData, guild_id: GuildId) -> bool {
+    let Some(bridge) = data.bridges.remove(&guild_id) else {
+        return false;
+    };
+    bridge.active.store(false, Ordering::Relaxed);
+    for side in &bridge.sides {
+        data.bridges.remove(&side.guild_id);
+        release(data, side).await;
+    }
+    true
+}
+
+async fn release(data: &UserData, side: &Side) {
+    let _ = side.track.stop();
+    if side.joined && !data.track_handles.contains_key(&side.guild_id) {
+        let _ = data.songbird.remove(side.guild_id).await;
+    }
+}
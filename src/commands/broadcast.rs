@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use log::{error, warn};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use serenity::{async_trait, builder::*};
+use songbird::{CoreEvent, Event, EventContext, EventHandler};
+
+use crate::{
+    COLOR_ERROR, COLOR_OK, UserData,
+    broadcast::{Broadcast, Format, Source},
+    pcm,
+};
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("broadcast")
+        .description("Stream this server's audio outside of Discord")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::SubCommand, "start", "Start streaming")
+                .add_sub_option(
+                    CreateCommandOption::new(CommandOptionType::String, "source", "What to stream")
+                        .add_string_choice("What the bot is playing", "playback")
+                        .add_string_choice("Everyone in the voice channel", "voice")
+                        .required(true),
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "format",
+                        "Stream encoding, default Opus",
+                    )
+                    .add_string_choice("Opus (Ogg)", "opus")
+                    .add_string_choice("MP3", "mp3"),
+                ),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "stop",
+            "Stop streaming",
+        ))
+}
+
+/// Feeds the voice channel into a [`Source::Voice`] broadcast.
+struct BroadcastTap {
+    broadcast: Arc<Broadcast>,
+}
+
+#[async_trait]
+impl EventHandler for BroadcastTap {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if !self.broadcast.is_active() {
+            return Some(Event::Cancel);
+        }
+
+        if let EventContext::VoiceTick(tick) = ctx {
+            let mix = pcm::mix_voice(tick);
+            if !mix.is_empty() {
+                self.broadcast.pcm.push_i16(&mix, 1.0);
+            }
+        }
+
+        None
+    }
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let Some(ResolvedOption {
+        name,
+        value: ResolvedValue::SubCommand(options),
+        ..
+    }) = interaction.data.options().first().cloned()
+    else {
+        warn!("broadcast interaction option not subcommand");
+        return Ok(());
+    };
+
+    let guild_id = interaction.guild_id.unwrap();
+    let embed = match name {
+        "start" => start(ctx, interaction, &options).await,
+        "stop" => {
+            let typemap = ctx.data.read().await;
+            let data = typemap.get::<UserData>().unwrap();
+            match data.broadcasts.lock().unwrap().remove(&guild_id) {
+                Some(broadcast) => {
+                    broadcast.stop();
+                    Ok(CreateEmbed::new().title("Broadcast stopped"))
+                }
+                None => Err("Nothing is being broadcast".to_string()),
+            }
+        }
+        _ => return Ok(()),
+    };
+
+    let embed = match embed {
+        Ok(embed) => embed.color(Colour::new(COLOR_OK)),
+        Err(e) => CreateEmbed::new()
+            .color(Colour::new(COLOR_ERROR))
+            .description(e)
+            .title("Error"),
+    };
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().embed(embed.timestamp(Timestamp::now())),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn start(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> Result<CreateEmbed, String> {
+    let guild_id = interaction.guild_id.unwrap();
+    let mut source = Source::Playback;
+    let mut format = Format::Opus;
+    for option in options {
+        match (option.name, &option.value) {
+            ("source", ResolvedValue::String("voice")) => source = Source::Voice,
+            ("format", ResolvedValue::String("mp3")) => format = Format::Mp3,
+            _ => {}
+        }
+    }
+
+    let mut typemap = ctx.data.write().await;
+    let data = typemap.get_mut::<UserData>().unwrap();
+    if data.broadcasts.lock().unwrap().contains_key(&guild_id) {
+        return Err("Already broadcasting, use `/broadcast stop` first".into());
+    }
+
+    let broadcast =
+        Broadcast::start(ctx, data.http.clone(), guild_id, source, format).map_err(|e| {
+            error!("failed to start broadcast: {e:?}");
+            format!("Could not start the broadcast: {e}")
+        })?;
+
+    if source == Source::Voice {
+        let channel_id = ctx
+            .cache
+            .guild(guild_id)
+            .and_then(|g| g.voice_states.get(&interaction.user.id)?.channel_id);
+        let Some(channel_id) = channel_id else {
+            broadcast.stop();
+            return Err("Join the voice channel you want to broadcast first".into());
+        };
+        match data.songbird.join(guild_id, channel_id).await {
+            Ok(handler_lock) => {
+                let mut handler = handler_lock.lock().await;
+                let _ = handler.deafen(false).await;
+                handler.add_global_event(
+                    CoreEvent::VoiceTick.into(),
+                    BroadcastTap {
+                        broadcast: broadcast.clone(),
+                    },
+                );
+            }
+            Err(e) => {
+                warn!("{}", e);
+                broadcast.stop();
+                return Err(format!("Could not join {}", channel_id.mention()));
+            }
+        }
+    }
+
+    data.broadcasts
+        .lock()
+        .unwrap()
+        .insert(guild_id, broadcast.clone());
+
+    let mut where_to = vec![];
+    if let Ok(base) = std::env::var("BROADCAST_PUBLIC_URL") {
+        where_to.push(format!(
+            "{}/{guild_id}.{}",
+            base.trim_end_matches('/'),
+            format.extension()
+        ));
+    }
+    if std::env::var("ICECAST_URL").is_ok() {
+        where_to.push("Pushing to Icecast".to_string());
+    }
+
+    Ok(CreateEmbed::new()
+        .title("Broadcasting")
+        .description(where_to.join("\n")))
+}
@@ -0,0 +1,94 @@
+use log::warn;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{
+    COLOR_ERROR, COLOR_OK, UserData, commands::play::TrackMeta, search::clock,
+    ytdlp::current_chapter,
+};
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("chapter")
+        .description("Jump to a chapter of the current track")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "to",
+                "next, prev, or a chapter number",
+            )
+            .required(true),
+        )
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let Some(ResolvedOption {
+        value: ResolvedValue::String(to),
+        ..
+    }) = interaction.data.options().first().cloned()
+    else {
+        warn!("chapter interaction option not string");
+        return Ok(());
+    };
+
+    let guild_id = interaction.guild_id.unwrap();
+    let track = {
+        let typemap = ctx.data.read().await;
+        let data = typemap.get::<UserData>().unwrap();
+        data.track_handles.get(&guild_id).cloned()
+    };
+
+    // Seeking a stream can take a moment.
+    interaction.defer(ctx).await?;
+    let result = async {
+        let track = track.ok_or("Nothing is playing")?;
+        let meta = track.data::<TrackMeta>();
+        let chapters = meta
+            .chapters
+            .get()
+            .filter(|c| !c.is_empty())
+            .ok_or("This track has no chapters")?;
+        let position = track
+            .get_info()
+            .await
+            .map_err(|_| "Nothing is playing")?
+            .position;
+        let current = current_chapter(chapters, position);
+
+        let target = match to.trim().to_lowercase().as_str() {
+            "next" => current.map_or(0, |n| n + 1),
+            "prev" | "previous" => current.map_or(0, |n| n.saturating_sub(1)),
+            n => n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .ok_or("Give next, prev or a chapter number")?,
+        };
+        let chapter = chapters.get(target).ok_or("No such chapter")?;
+        track
+            .seek_async(chapter.start)
+            .await
+            .map_err(|_| "Could not seek in this track")?;
+        Ok::<_, &str>(
+            CreateEmbed::new()
+                .color(Colour::new(COLOR_OK))
+                .title(format!("Chapter {}/{}", target + 1, chapters.len()))
+                .description(format!("{} {}", clock(chapter.start), chapter.title)),
+        )
+    }
+    .await;
+    let embed = result.unwrap_or_else(|e| {
+        CreateEmbed::new()
+            .color(Colour::new(COLOR_ERROR))
+            .description(e)
+            .title("Error")
+    });
+
+    interaction
+        .create_followup(
+            ctx,
+            CreateInteractionResponseFollowup::new().embed(embed.timestamp(Timestamp::now())),
+        )
+        .await?;
+    Ok(())
+}
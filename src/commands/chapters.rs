@@ -0,0 +1,68 @@
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{
+    COLOR_ERROR, COLOR_OK, UserData, commands::play::TrackMeta, search::clock,
+    ytdlp::current_chapter,
+};
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("chapters").description("List the chapters of the current track")
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let guild_id = interaction.guild_id.unwrap();
+    let track = {
+        let typemap = ctx.data.read().await;
+        let data = typemap.get::<UserData>().unwrap();
+        data.track_handles.get(&guild_id).cloned()
+    };
+
+    let embed = match track {
+        None => Err("Nothing is playing"),
+        Some(track) => {
+            let meta = track.data::<TrackMeta>();
+            match meta.chapters.get() {
+                Some(chapters) if !chapters.is_empty() => {
+                    let position = track.get_info().await.map(|i| i.position);
+                    let current = position.ok().and_then(|p| current_chapter(chapters, p));
+                    let lines: Vec<String> = chapters
+                        .iter()
+                        .enumerate()
+                        .map(|(i, c)| {
+                            let line = format!("`{}.` {} {}", i + 1, clock(c.start), c.title);
+                            if Some(i) == current {
+                                format!("**{line}**")
+                            } else {
+                                line
+                            }
+                        })
+                        .collect();
+                    Ok(CreateEmbed::new()
+                        .color(Colour::new(COLOR_OK))
+                        .title(format!("Chapters of {}", meta.title))
+                        .description(lines.join("\n")))
+                }
+                Some(_) => Err("This track has no chapters"),
+                None => Err("No chapters known for this track (yet)"),
+            }
+        }
+    };
+    let embed = embed.unwrap_or_else(|e| {
+        CreateEmbed::new()
+            .color(Colour::new(COLOR_ERROR))
+            .description(e)
+            .title("Error")
+    });
+
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().embed(embed.timestamp(Timestamp::now())),
+            ),
+        )
+        .await?;
+    Ok(())
+}
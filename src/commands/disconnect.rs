@@ -13,10 +13,15 @@ pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(),
     let guild_id = interaction.guild_id.unwrap();
     let mut typemap = ctx.data.write().await;
     let data = typemap.get_mut::<UserData>().unwrap();
+    crate::queue::clear(data, guild_id);
     let track = data.track_handles.get_mut(&guild_id);
     if let Some(track) = track {
         let _ = track.stop();
     }
+    super::bridge::teardown(data, guild_id).await;
+    if let Some(broadcast) = data.broadcasts.lock().unwrap().remove(&guild_id) {
+        broadcast.stop();
+    }
     let _ = data.songbird.remove(guild_id).await;
 
     interaction
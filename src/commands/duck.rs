@@ -0,0 +1,148 @@
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{COLOR_ERROR, COLOR_OK, UserData};
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("duck")
+        .description("Turn the music down while people are talking (Manage Server)")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "enabled",
+            "Whether to duck at all; the bot has to listen to the channel for it",
+        ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "threshold",
+                "How loud talking has to be, in dBFS, default -45",
+            )
+            // Only takes unsigned, but Discord goes by the number either way.
+            .min_number_value(-80.0)
+            .max_number_value(0.0),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "amount",
+                "How much quieter the music gets, in percent, default 70",
+            )
+            .min_int_value(0)
+            .max_int_value(100),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "attack",
+                "Milliseconds to turn the music down, default 100",
+            )
+            .min_int_value(0)
+            .max_int_value(5000),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "release",
+                "Milliseconds to bring the music back, default 800",
+            )
+            .min_int_value(0)
+            .max_int_value(10000),
+        )
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let guild_id = interaction.guild_id.unwrap();
+    let (settings, call, volume) = {
+        let typemap = ctx.data.read().await;
+        let data = typemap.get::<UserData>().unwrap();
+        (
+            data.settings.clone(),
+            data.songbird.get(guild_id),
+            data.volume(guild_id),
+        )
+    };
+
+    let options = interaction.data.options();
+    let is_admin = interaction
+        .member
+        .as_ref()
+        .and_then(|m| m.permissions)
+        .is_some_and(|p| p.manage_guild());
+    if !options.is_empty() && !is_admin {
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new().embed(
+                        CreateEmbed::new()
+                            .color(Colour::new(COLOR_ERROR))
+                            .title("Error")
+                            .description("You need Manage Server for that")
+                            .timestamp(Timestamp::now()),
+                    ),
+                ),
+            )
+            .await?;
+        return Ok(());
+    }
+    if !options.is_empty() {
+        settings.update(guild_id, |s| {
+            for option in options {
+                match (option.name, option.value) {
+                    ("enabled", ResolvedValue::Boolean(enabled)) => s.auto_duck = enabled,
+                    ("threshold", ResolvedValue::Integer(n)) => s.duck_threshold = n as i32,
+                    ("amount", ResolvedValue::Integer(n)) => s.duck_amount = n as u32,
+                    ("attack", ResolvedValue::Integer(n)) => s.duck_attack_ms = n as u64,
+                    ("release", ResolvedValue::Integer(n)) => s.duck_release_ms = n as u64,
+                    _ => {}
+                }
+            }
+        });
+    }
+
+    let current = settings.get(guild_id);
+    // Turning it off leaves the bot undeafened, like recordings do; the
+    // listener notices by itself and stops.
+    if current.auto_duck
+        && let Some(call) = call
+    {
+        let mut handler = call.lock().await;
+        let _ = handler.deafen(false).await;
+        crate::ducking::listen(
+            &mut handler,
+            ctx.cache.clone(),
+            settings.clone(),
+            volume,
+            guild_id,
+        );
+    }
+
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().embed(
+                    CreateEmbed::new()
+                        .color(Colour::new(COLOR_OK))
+                        .title("Ducking")
+                        .field(
+                            "Enabled",
+                            if current.auto_duck { "on" } else { "off" },
+                            true,
+                        )
+                        .field(
+                            "Threshold",
+                            format!("{} dBFS", current.duck_threshold),
+                            true,
+                        )
+                        .field("Amount", format!("{}%", current.duck_amount), true)
+                        .field("Attack", format!("{}ms", current.duck_attack_ms), true)
+                        .field("Release", format!("{}ms", current.duck_release_ms), true)
+                        .timestamp(Timestamp::now()),
+                ),
+            ),
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use log::warn;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{
+    COLOR_ERROR, COLOR_OK, UserData,
+    history::Play,
+    queue::{self, QueueEntry},
+    search::clock,
+};
+
+const PAGE_SIZE: usize = 5;
+
+/// Page buttons' `custom_id` is `history_page:<total>:<prev|next>:<offset>`.
+/// `total` is how many plays there were when `/history` ran, so later ones
+/// don't shift the pages.
+pub const PAGE_PREFIX: &str = "history_page:";
+/// Re-queue buttons' `custom_id` is `history_queue:<key>`, see [`key`].
+/// Positions would shift when the history gets trimmed.
+pub const QUEUE_PREFIX: &str = "history_queue:";
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("history").description("Show what was played here recently")
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let guild_id = interaction.guild_id.unwrap();
+    let plays = plays(ctx, guild_id).await;
+    let message = page(&plays, plays.len(), 0);
+    interaction
+        .create_response(ctx, CreateInteractionResponse::Message(message))
+        .await?;
+    Ok(())
+}
+
+/// Handles the page and re-queue buttons.
+pub async fn run_component(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+) -> Result<(), serenity::Error> {
+    let guild_id = interaction.guild_id.unwrap();
+    let custom_id = interaction.data.custom_id.as_str();
+    if let Some(wanted) = custom_id.strip_prefix(QUEUE_PREFIX) {
+        // Joining and starting the track can take a moment.
+        interaction.defer(ctx).await?;
+        let plays = plays(ctx, guild_id).await;
+        let play = plays.iter().find(|p| key(p) == wanted);
+        let embed = match play {
+            Some(play) => requeue(ctx, interaction, play).await,
+            None => Err("That track isn't in the history anymore".to_string()),
+        };
+        let embed = match embed {
+            Ok(description) => CreateEmbed::new()
+                .color(Colour::new(COLOR_OK))
+                .title("Queued")
+                .description(description),
+            Err(e) => CreateEmbed::new()
+                .color(Colour::new(COLOR_ERROR))
+                .title("Error")
+                .description(e),
+        };
+        interaction
+            .create_followup(
+                ctx,
+                CreateInteractionResponseFollowup::new().embed(embed.timestamp(Timestamp::now())),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let plays = plays(ctx, guild_id).await;
+
+    let Some((total, offset)) = custom_id.strip_prefix(PAGE_PREFIX).and_then(|s| {
+        let mut parts = s.splitn(3, ':');
+        let total = parts.next()?.parse().ok()?;
+        let _direction = parts.next()?;
+        Some((total, parts.next()?.parse().ok()?))
+    }) else {
+        warn!("malformed history id {custom_id}");
+        return Ok(());
+    };
+    let message = page(&plays, total, offset);
+    interaction
+        .create_response(ctx, CreateInteractionResponse::UpdateMessage(message))
+        .await?;
+    Ok(())
+}
+
+/// Newest first.
+async fn plays(ctx: &Context, guild_id: GuildId) -> Vec<Play> {
+    let history = {
+        let typemap = ctx.data.read().await;
+        typemap.get::<UserData>().unwrap().history.clone()
+    };
+    tokio::task::spawn_blocking(move || history.plays(guild_id, None))
+        .await
+        .unwrap_or_default()
+}
+
+/// The plays from `offset` on, of the first `total` the guild had.
+fn page(plays: &[Play], total: usize, offset: usize) -> CreateInteractionResponseMessage {
+    let total = total.min(plays.len());
+    // Plays since the first page was shown come first, skip them.
+    let newer = plays.len() - total;
+    let shown: Vec<(usize, &Play)> = plays
+        .iter()
+        .enumerate()
+        .skip(newer + offset)
+        .take(PAGE_SIZE)
+        .map(|(i, play)| (i - newer, play))
+        .collect();
+    if shown.is_empty() {
+        return CreateInteractionResponseMessage::new()
+            .embed(
+                CreateEmbed::new()
+                    .color(Colour::new(COLOR_OK))
+                    .title("History")
+                    .description("Nothing was played here yet")
+                    .timestamp(Timestamp::now()),
+            )
+            .components(vec![]);
+    }
+
+    let lines: Vec<String> = shown
+        .iter()
+        .map(|(i, play)| {
+            let title = match Url::parse(&play.url) {
+                Ok(url) if url.scheme() != "file" => format!("[{}]({url})", play.title),
+                _ => play.title.clone(),
+            };
+            let by = match play.requested_by {
+                Some(user) => format!("<@{user}>"),
+                None => "radio".to_string(),
+            };
+            format!(
+                "{}. {title}\n-# {by} · <t:{}:R> · listened {}",
+                i + 1,
+                play.started_at,
+                clock(play.listened)
+            )
+        })
+        .collect();
+    let queue_buttons = shown
+        .iter()
+        .map(|(i, play)| {
+            CreateButton::new(format!("{QUEUE_PREFIX}{}", key(play)))
+                .label(format!("Queue {}", i + 1))
+                .style(ButtonStyle::Primary)
+        })
+        .collect();
+    let page_button = |direction: &str, offset: Option<usize>| {
+        CreateButton::new(format!(
+            "{PAGE_PREFIX}{total}:{direction}:{}",
+            offset.unwrap_or(0)
+        ))
+        .style(ButtonStyle::Secondary)
+        .disabled(offset.is_none())
+    };
+    let prev = offset.checked_sub(PAGE_SIZE);
+    let next = Some(offset + PAGE_SIZE).filter(|&o| o < total);
+
+    CreateInteractionResponseMessage::new()
+        .embed(
+            CreateEmbed::new()
+                .color(Colour::new(COLOR_OK))
+                .title("History")
+                .description(lines.join("\n"))
+                .footer(CreateEmbedFooter::new(format!(
+                    "{}–{} of {total}",
+                    offset + 1,
+                    offset + shown.len()
+                )))
+                .timestamp(Timestamp::now()),
+        )
+        .components(vec![
+            CreateActionRow::Buttons(queue_buttons),
+            CreateActionRow::Buttons(vec![
+                page_button("prev", prev).label("Previous"),
+                page_button("next", next).label("Next"),
+            ]),
+        ])
+}
+
+/// Tells plays apart: when it started and a hash of what it was.
+fn key(play: &Play) -> String {
+    format!(
+        "{}:{}",
+        play.started_at,
+        hex::encode(&Sha256::digest(&play.url)[..4])
+    )
+}
+
+/// Queues `play` again in the clicker's voice channel.
+async fn requeue(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    play: &Play,
+) -> Result<String, String> {
+    let guild_id = interaction.guild_id.unwrap();
+    let Some(channel_id) = ctx
+        .cache
+        .guild(guild_id)
+        .and_then(|g| g.voice_states.get(&interaction.user.id)?.channel_id)
+    else {
+        return Err("Join a voice channel first".into());
+    };
+    let url = Url::parse(&play.url).map_err(|e| e.to_string())?;
+    if url.to_file_path().is_ok_and(|p| !p.exists()) {
+        return Err("That file isn't around anymore".into());
+    }
+    let entries = vec![QueueEntry {
+        url,
+        title: play.title.clone(),
+        requested_in: interaction.channel_id,
+        requested_by: Some(interaction.user.id),
+    }];
+    let lines = queue::describe(&entries);
+
+    super::play::join(ctx, guild_id, channel_id).await;
+    let positions = queue::enqueue(ctx, guild_id, entries).await;
+    if let Some(e) = queue::failed(&positions) {
+        return Err(e);
+    }
+    Ok(queue::positions(&positions, lines))
+}
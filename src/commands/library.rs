@@ -0,0 +1,163 @@
+use log::warn;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{
+    COLOR_ERROR, COLOR_OK, UserData,
+    library::LibraryTrack,
+    queue::{self, QueueEntry},
+};
+
+/// Most tracks a single `/library` command queues.
+const MAX_QUEUED: usize = 100;
+const MAX_LISTED: usize = 10;
+
+pub fn register() -> CreateCommand {
+    let query = |name: &str, description: &str| {
+        CreateCommandOption::new(CommandOptionType::SubCommand, name, description).add_sub_option(
+            CreateCommandOption::new(CommandOptionType::String, "query", "What to look for")
+                .required(true),
+        )
+    };
+    CreateCommand::new("library")
+        .description("Play music from the local library")
+        .add_option(query("search", "List matching tracks"))
+        .add_option(query("play", "Play the best matching track"))
+        .add_option(query("album", "Play a whole album"))
+        .add_option(query("artist", "Play everything by an artist"))
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let Some(ResolvedOption {
+        name,
+        value: ResolvedValue::SubCommand(options),
+        ..
+    }) = interaction.data.options().first().cloned()
+    else {
+        warn!("library interaction option not subcommand");
+        return Ok(());
+    };
+    let Some(ResolvedOption {
+        value: ResolvedValue::String(query),
+        ..
+    }) = options.first().cloned()
+    else {
+        return Ok(());
+    };
+
+    let library = {
+        let typemap = ctx.data.read().await;
+        typemap.get::<UserData>().unwrap().library.clone()
+    };
+    let result = match library {
+        None => Err("No music library is set up".to_string()),
+        Some(library) => match name {
+            "search" => Ok(list(query, library.search(query))),
+            "play" => {
+                let found = library.search(query);
+                play(ctx, interaction, found.into_iter().take(1).collect()).await
+            }
+            "album" => play(ctx, interaction, library.album(query)).await,
+            "artist" => play(ctx, interaction, library.artist(query)).await,
+            _ => return Ok(()),
+        },
+    };
+
+    let (embed, cover) = match result {
+        Ok((embed, cover)) => (embed.color(Colour::new(COLOR_OK)), cover),
+        Err(e) => (
+            CreateEmbed::new()
+                .color(Colour::new(COLOR_ERROR))
+                .description(e)
+                .title("Error"),
+            None,
+        ),
+    };
+    let mut message =
+        CreateInteractionResponseMessage::new().embed(embed.timestamp(Timestamp::now()));
+    if let Some(cover) = cover {
+        message = message.add_file(cover);
+    }
+    interaction
+        .create_response(ctx, CreateInteractionResponse::Message(message))
+        .await?;
+
+    Ok(())
+}
+
+fn list(query: &str, found: Vec<LibraryTrack>) -> (CreateEmbed, Option<CreateAttachment>) {
+    let mut lines: Vec<String> = found
+        .iter()
+        .take(MAX_LISTED)
+        .map(|t| {
+            let album = t
+                .album
+                .as_ref()
+                .map(|a| format!(" · {a}"))
+                .unwrap_or_default();
+            format!("{}{album}", t.display())
+        })
+        .collect();
+    if found.is_empty() {
+        lines.push("Nothing found".to_string());
+    } else if found.len() > MAX_LISTED {
+        lines.push(format!("...and {} more", found.len() - MAX_LISTED));
+    }
+    (
+        CreateEmbed::new()
+            .title(format!("Library results for {query}"))
+            .description(lines.join("\n")),
+        None,
+    )
+}
+
+/// Queues `tracks` in the caller's voice channel, showing the first one's
+/// cover art.
+async fn play(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    tracks: Vec<LibraryTrack>,
+) -> Result<(CreateEmbed, Option<CreateAttachment>), String> {
+    if tracks.is_empty() {
+        return Err("Nothing in the library matches".into());
+    }
+    let guild_id = interaction.guild_id.unwrap();
+    let Some(channel_id) = ctx
+        .cache
+        .guild(guild_id)
+        .and_then(|g| g.voice_states.get(&interaction.user.id)?.channel_id)
+    else {
+        return Err("Join a voice channel first".into());
+    };
+
+    let cover = match &tracks[0].cover {
+        Some(path) => CreateAttachment::path(path).await.ok(),
+        None => None,
+    };
+    let entries: Vec<QueueEntry> = tracks
+        .iter()
+        .take(MAX_QUEUED)
+        .map(|t| QueueEntry {
+            url: t.url(),
+            title: t.display(),
+            requested_in: interaction.channel_id,
+            requested_by: Some(interaction.user.id),
+        })
+        .collect();
+    let lines = queue::describe(&entries);
+
+    super::play::join(ctx, guild_id, channel_id).await;
+    let positions = queue::enqueue(ctx, guild_id, entries).await;
+    if let Some(e) = queue::failed(&positions) {
+        return Err(e);
+    }
+
+    let mut embed = CreateEmbed::new()
+        .title("Queued")
+        .description(queue::positions(&positions, lines));
+    if let Some(cover) = &cover {
+        embed = embed.thumbnail(format!("attachment://{}", cover.filename));
+    }
+    Ok((embed, cover))
+}
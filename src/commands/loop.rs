@@ -3,7 +3,7 @@ use serenity::model::prelude::*;
 use serenity::prelude::*;
 use songbird::tracks::LoopState;
 
-use crate::{COLOR_OK, UserData};
+use crate::{COLOR_ERROR, COLOR_OK, UserData, commands::play::TrackMeta};
 
 pub fn register() -> CreateCommand {
     CreateCommand::new("loop").description("Toggle looping (default on)")
@@ -15,6 +15,23 @@ pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(),
     let mut typemap = d.write().await;
     let data = typemap.get_mut::<UserData>().unwrap();
     if let Some(track) = data.track_handles.get_mut(&guild_id) {
+        if track.data::<TrackMeta>().is_live() {
+            interaction
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new().embed(
+                            CreateEmbed::new()
+                                .color(Colour::new(COLOR_ERROR))
+                                .description("Live streams can't be looped")
+                                .title("Error")
+                                .timestamp(Timestamp::now()),
+                        ),
+                    ),
+                )
+                .await?;
+            return Ok(());
+        }
         let loops: LoopState = track.get_info().await.unwrap().loops;
         let is_looping = match loops {
             LoopState::Finite(0) => false,
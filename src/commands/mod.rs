@@ -1,30 +1,23 @@
-use serenity::async_trait;
-use songbird::events::{Event, EventContext, EventHandler};
-
+pub mod autocomplete;
+pub mod bridge;
+pub mod broadcast;
+pub mod chapter;
+pub mod chapters;
 pub mod disconnect;
+pub mod duck;
+pub mod history;
+pub mod library;
 pub mod r#loop;
 pub mod pause;
 pub mod play;
-//pub mod record;
+pub mod playlist;
+pub mod radio;
+pub mod record;
+pub mod say;
 pub mod search;
+pub mod segments;
+pub mod sound;
+pub mod stats;
 pub mod stop;
+pub mod tts;
 pub mod volume;
-
-struct TrackErrorNotifier;
-
-#[async_trait]
-impl EventHandler for TrackErrorNotifier {
-    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
-        if let EventContext::Track(track_list) = ctx {
-            for (state, handle) in *track_list {
-                println!(
-                    "Track {:?} encountered an error: {:?}",
-                    handle.uuid(),
-                    state.playing
-                );
-            }
-        }
-
-        None
-    }
-}
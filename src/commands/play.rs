@@ -1,11 +1,49 @@
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
 use log::{error, warn};
 use serenity::builder::*;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
-use songbird::{TrackEvent, input::YoutubeDl};
+use songbird::{Call, tracks::TrackHandle};
+use tokio::sync::watch;
 use url::*;
 
-use crate::{COLOR_ERROR, COLOR_OK, UserData, commands::TrackErrorNotifier};
+use crate::{
+    COLOR_ERROR, COLOR_OK, UserData,
+    queue::{self, QueueEntry},
+    ytdlp::{Chapter, current_chapter},
+};
+
+/// How often the "Now Playing" embed checks for a new chapter.
+const CHAPTER_POLL: Duration = Duration::from_secs(5);
+
+/// Attached to every music track, see [`songbird::tracks::TrackHandle::data`].
+pub struct TrackMeta {
+    pub url: Url,
+    pub title: String,
+    /// What a live stream is playing right now, `None` for regular tracks.
+    pub stream_title: Option<watch::Receiver<Option<String>>>,
+    /// Where errors about the track get reported.
+    pub requested_in: ChannelId,
+    /// `None` for radio picks.
+    pub requested_by: Option<UserId>,
+    /// Unix seconds.
+    pub started_at: i64,
+    pub attempt: queue::Attempt,
+    /// Filled in from yt-dlp shortly after the track starts, empty for
+    /// anything else.
+    pub chapters: OnceLock<Vec<Chapter>>,
+}
+
+impl TrackMeta {
+    /// Live streams can't be seeked or looped.
+    pub fn is_live(&self) -> bool {
+        self.stream_title.is_some()
+    }
+}
 
 pub fn register() -> CreateCommand {
     CreateCommand::new("play")
@@ -22,7 +60,8 @@ pub fn register() -> CreateCommand {
                     "link",
                     "The link of the audio",
                 )
-                .required(true),
+                .required(true)
+                .set_autocomplete(true),
             ),
         )
         .add_option(
@@ -46,7 +85,7 @@ pub async fn run_command(
     ctx: &Context,
     interaction: &CommandInteraction,
 ) -> Result<(), serenity::Error> {
-    let mut filename = String::new();
+    let mut attachment: Option<Attachment> = None;
     let mut final_url: Option<Url> = None;
     let mut search_str = String::new();
     if let Some(ResolvedOption {
@@ -59,50 +98,54 @@ pub async fn run_command(
             ..
         }) = options.first().cloned()
         {
-            let url = Url::parse(url_str);
-            if url.is_err() {
-                search_str = url_str.to_string();
+            if let Ok(url) = Url::parse(url_str) {
+                final_url = Some(url);
             } else {
-                final_url = Some(url.unwrap());
+                search_str = url_str.to_string();
             }
         } else if let Some(ResolvedOption {
             value: ResolvedValue::Attachment(a),
             ..
         }) = options.first().cloned()
         {
-            filename = a.filename.clone();
-            let url = Url::parse(&a.url);
-            if let Err(err) = url {
-                interaction
-                    .create_response(
-                        ctx,
-                        CreateInteractionResponse::Message(
-                            CreateInteractionResponseMessage::new().embed(
-                                CreateEmbed::new()
-                                    .color(Colour::new(COLOR_ERROR))
-                                    .description(format!("Not a valid attachment URL: {}", err))
-                                    .title("Error")
-                                    .timestamp(Timestamp::now()),
-                            ),
-                        ),
-                    )
-                    .await?;
-            } else {
-                final_url = Some(url.unwrap());
-            }
+            attachment = Some(a.clone());
         }
     } else {
         warn!("play interaction option not subcommand");
         return Ok(());
     }
+    if let Some(url) = &final_url {
+        let allowed = {
+            let typemap = ctx.data.read().await;
+            let data = typemap.get::<UserData>().unwrap();
+            crate::source::allowed(url, data.library.as_deref(), &data.attachments)
+        };
+        if !allowed {
+            interaction
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new().embed(
+                            CreateEmbed::new()
+                                .color(Colour::new(COLOR_ERROR))
+                                .description("Only http and https links can be played")
+                                .title("Error")
+                                .timestamp(Timestamp::now()),
+                        ),
+                    ),
+                )
+                .await?;
+            return Ok(());
+        }
+    }
     let mut is_search = false;
-    if final_url.is_none() {
+    if final_url.is_none() && attachment.is_none() {
         is_search = true;
         // warn!("url none");
     }
 
     if is_search {
-        let resp = search(&search_str).await;
+        let resp = super::search::search(ctx, &search_str).await;
         interaction.create_response(ctx, resp).await?;
         return Ok(());
     }
@@ -138,104 +181,269 @@ pub async fn run_command(
         }
     };
 
-    let interact_resp = play_audio(ctx, guild_id, channel_id, final_url, filename)
-        .await
-        .unwrap();
+    // Saving uploads, looking up titles, joining and probing the link can
+    // easily take longer than the 3s Discord gives us.
+    interaction.defer(ctx).await?;
+
+    // Uploads are saved first, so they still play once the CDN link expires.
+    let mut filename = String::new();
+    if let Some(a) = &attachment {
+        let (http, cache) = {
+            let typemap = ctx.data.read().await;
+            let data = typemap.get::<UserData>().unwrap();
+            (data.http.clone(), data.attachments.clone())
+        };
+        match cache.store(&http, &a.url, &a.filename, a.size.into()).await {
+            Ok(path) => {
+                filename = a.filename.clone();
+                final_url = Url::from_file_path(path).ok();
+            }
+            Err(e) => {
+                warn!("could not cache attachment {}: {e:?}", a.filename);
+                let embed = CreateEmbed::new()
+                    .color(Colour::new(COLOR_ERROR))
+                    .description(format!("{e:#}"))
+                    .title("Error")
+                    .timestamp(Timestamp::now());
+                interaction
+                    .create_followup(ctx, CreateInteractionResponseFollowup::new().embed(embed))
+                    .await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let (embed, track) = play_audio(
+        ctx,
+        guild_id,
+        channel_id,
+        interaction.channel_id,
+        interaction.user.id,
+        final_url,
+        filename,
+    )
+    .await;
 
-    interaction.create_response(ctx, interact_resp).await?;
+    interaction
+        .create_followup(ctx, CreateInteractionResponseFollowup::new().embed(embed))
+        .await?;
+    if let Some(track) = track {
+        follow_stream_title(ctx, interaction, &track).await;
+        follow_chapters(ctx, interaction, &track).await;
+    }
     Ok(())
 }
 
+/// Keeps the "Now Playing" embed of a live stream showing what's on air,
+/// until the stream ends.
+async fn follow_stream_title(ctx: &Context, interaction: &CommandInteraction, track: &TrackHandle) {
+    let meta = track.data::<TrackMeta>();
+    let Some(mut on_air) = meta.stream_title.clone() else {
+        return;
+    };
+    // Interaction tokens expire, editing the message itself doesn't.
+    let Ok(mut message) = interaction.get_response(ctx).await else {
+        return;
+    };
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        while on_air.changed().await.is_ok() {
+            let Some(now) = on_air.borrow_and_update().clone() else {
+                continue;
+            };
+            let embed = CreateEmbed::new()
+                .color(Colour::new(COLOR_OK))
+                .title("Now Playing")
+                .description(format!("[{}]({})\nOn air: {now}", meta.title, meta.url))
+                .timestamp(Timestamp::now());
+            if let Err(e) = message.edit(&ctx, EditMessage::new().embed(embed)).await {
+                warn!("could not update now playing: {e}");
+                break;
+            }
+        }
+    });
+}
+
+/// Keeps the "Now Playing" embed showing the current chapter of a video
+/// that has them, until the track is over.
+async fn follow_chapters(ctx: &Context, interaction: &CommandInteraction, track: &TrackHandle) {
+    if track.data::<TrackMeta>().is_live() {
+        return;
+    }
+    let Ok(mut message) = interaction.get_response(ctx).await else {
+        return;
+    };
+
+    let (ctx, track) = (ctx.clone(), track.clone());
+    tokio::spawn(async move {
+        let meta = track.data::<TrackMeta>();
+        let mut shown = None;
+        loop {
+            tokio::time::sleep(CHAPTER_POLL).await;
+            let Ok(info) = track.get_info().await else {
+                break;
+            };
+            if info.playing.is_done() {
+                break;
+            }
+            let Some(chapters) = meta.chapters.get() else {
+                continue;
+            };
+            if chapters.is_empty() {
+                break;
+            }
+            let current = current_chapter(chapters, info.position);
+            if current == shown {
+                continue;
+            }
+            shown = current;
+            let Some(n) = current else {
+                continue;
+            };
+            let embed = CreateEmbed::new()
+                .color(Colour::new(COLOR_OK))
+                .title("Now Playing")
+                .description(format!(
+                    "[{}]({})\nChapter {}/{}: {}",
+                    meta.title,
+                    meta.url,
+                    n + 1,
+                    chapters.len(),
+                    chapters[n].title
+                ))
+                .timestamp(Timestamp::now());
+            if let Err(e) = message.edit(&ctx, EditMessage::new().embed(embed)).await {
+                warn!("could not update now playing: {e}");
+                break;
+            }
+        }
+    });
+}
+
 async fn play_audio(
     ctx: &Context,
     guild_id: GuildId,
     channel_id: ChannelId,
+    requested_in: ChannelId,
+    requested_by: UserId,
     url: Option<Url>,
     filename: String,
-) -> Result<CreateInteractionResponse, ()> {
-    let mut typemap = ctx.data.write().await;
-    let data = typemap.get_mut::<UserData>().unwrap();
-    let manager = &data.songbird;
-    if let Some(track) = data.track_handles.get_mut(&guild_id) {
-        let _ = track.stop();
-    }
+) -> (CreateEmbed, Option<TrackHandle>) {
+    let url = url.unwrap();
+    let title = track_title(ctx, &url, filename).await;
 
-    let call = manager.join(guild_id, channel_id).await;
-    if let Ok(handler_lock) = call {
-        let mut handler = handler_lock.lock().await;
-        handler.add_global_event(TrackEvent::Error.into(), TrackErrorNotifier);
-        let _ = handler.deafen(true).await;
-    } else if let Err(e) = call {
-        warn!("{}", e);
-    }
-
-    if let Some(handler_lock) = data.songbird.get(guild_id) {
-        let mut handler = handler_lock.lock().await;
-
-        let url = url.unwrap();
-        let src = YoutubeDl::new(data.http.clone(), url.to_string());
-        let song = handler.play_input(src.into());
-
-        let mut title = String::new();
+    join(ctx, guild_id, channel_id).await;
 
-        if url.to_string().contains("cdn.discordapp.com") {
-            title = filename;
-        } else if url.to_string().contains("youtu") {
-            let pairs = url.query_pairs();
-            for pair in pairs {
-                if pair.0 == "v" {
-                    if let Ok(title1) = crate::youtube::get_video_title(&pair.1).await {
-                        title = title1;
-                    }
-                }
-            }
-        } else {
-            title = url.to_string();
+    let entry = QueueEntry {
+        url: url.clone(),
+        title: title.clone(),
+        requested_in,
+        requested_by: Some(requested_by),
+    };
+    match queue::play(ctx, guild_id, entry).await {
+        Ok(track) => {
+            let meta = track.data::<TrackMeta>();
+            let line = queue::describe(&[QueueEntry {
+                url: meta.url.clone(),
+                title: meta.title.clone(),
+                requested_in,
+                requested_by: meta.requested_by,
+            }]);
+            let embed = CreateEmbed::new()
+                .color(Colour::new(COLOR_OK))
+                .title("Now Playing")
+                .description(line.concat())
+                .timestamp(Timestamp::now());
+            (embed, Some(track))
         }
-
-        // TODO: persist loop setting
-        let _ = song.enable_loop();
-        let _ = song.set_volume(0.5);
-        data.track_handles.insert(guild_id, song);
-        Ok(CreateInteractionResponse::Message(
-            CreateInteractionResponseMessage::new().embed(
-                CreateEmbed::new()
-                    .color(Colour::new(COLOR_OK))
-                    .title("Now Playing")
-                    .description(format!("[{}]({})", title, url))
-                    .timestamp(Timestamp::now()),
-            ),
-        ))
-    } else {
-        // TODO: error
-        error!("Songbird get none");
-        // check_msg(
-        //     msg.channel_id
-        //         .say(&ctx.http, "Not in a voice channel to play in")
-        //         .await,
-        // );
-        Ok(CreateInteractionResponse::Message(
-            CreateInteractionResponseMessage::new().embed(
+        Err(e) => {
+            warn!("could not play {url} in {guild_id}: {e}");
+            (
                 CreateEmbed::new()
                     .color(Colour::new(COLOR_ERROR))
                     .title("Error")
-                    .description("Could not get Songbird manager for guild")
+                    .description(e)
                     .timestamp(Timestamp::now()),
-            ),
-        ))
+                None,
+            )
+        }
     }
 }
 
+/// Joins `channel_id`, or moves there if already in another channel.
+/// Returns the call, or `None` if joining failed.
+pub async fn join(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Option<Arc<Mutex<Call>>> {
+    // Joining waits on Discord, the data lock is only held to look around.
+    let (songbird, listening, settings, volume) = {
+        let typemap = ctx.data.read().await;
+        let data = typemap.get::<UserData>().unwrap();
+        (
+            data.songbird.clone(),
+            data.is_listening(guild_id),
+            data.settings.clone(),
+            data.volume(guild_id),
+        )
+    };
+    let call = match songbird.join(guild_id, channel_id).await {
+        Ok(call) => call,
+        Err(e) => {
+            warn!("{}", e);
+            return None;
+        }
+    };
+    {
+        let mut handler = call.lock().await;
+        if !listening {
+            let _ = handler.deafen(true).await;
+        }
+        if settings.get(guild_id).auto_duck {
+            crate::ducking::listen(&mut handler, ctx.cache.clone(), settings, volume, guild_id);
+        }
+    }
+    Some(call)
+}
+
+/// What to show for a track: the attachment's file name, the video title for
+/// YouTube links, or the link itself.
+pub async fn track_title(ctx: &Context, url: &Url, filename: String) -> String {
+    if !filename.is_empty() {
+        return filename;
+    }
+    if let Some(id) = crate::youtube::video_id(url) {
+        let search = {
+            let typemap = ctx.data.read().await;
+            typemap.get::<UserData>().unwrap().search.clone()
+        };
+        match search.video_title(&id).await {
+            Ok(title) => return title,
+            Err(e) => warn!("no title for {id}: {e:?}"),
+        }
+    }
+    url.to_string()
+}
+
 pub async fn run_component(
     ctx: &Context,
     interaction: &ComponentInteraction,
 ) -> Result<(), serenity::Error> {
-    let final_url: Option<Url>;
+    if super::search::is_expired(&interaction.data.custom_id) {
+        interaction
+            .create_response(ctx, super::search::expired())
+            .await?;
+        return Ok(());
+    }
+
+    let urls: Vec<Url>;
     if let ComponentInteractionDataKind::StringSelect { values } = &interaction.data.kind {
-        let id = values[0].clone();
-        let url = format!("https://youtube.com/watch?v={}", id);
-        let url = Url::parse(&url);
-        final_url = Some(url.unwrap());
+        urls = values
+            .iter()
+            .filter_map(|id| Url::parse(&format!("https://youtube.com/watch?v={}", id)).ok())
+            .collect();
     } else {
         error!("component interaction not string??");
         return Ok(());
@@ -271,43 +479,39 @@ pub async fn run_component(
             // (GuildId::new(0), ChannelId::new(0))
         }
     };
-    let interact_resp = play_audio(ctx, guild_id, channel_id, final_url, String::new())
-        .await
-        .unwrap();
-
-    interaction.create_response(ctx, interact_resp).await?;
-    Ok(())
-}
+    // Looking up titles can take a moment with several videos.
+    interaction.defer(ctx).await?;
 
-fn truncate(s: &str, max_chars: usize) -> &str {
-    match s.char_indices().nth(max_chars) {
-        None => s,
-        Some((idx, _)) => &s[..idx],
+    let mut entries = vec![];
+    for url in urls {
+        let title = track_title(ctx, &url, String::new()).await;
+        entries.push(QueueEntry {
+            url,
+            title,
+            requested_in: interaction.channel_id,
+            requested_by: Some(interaction.user.id),
+        });
     }
-}
-
-pub async fn search(search_str: &str) -> CreateInteractionResponse {
-    let results = crate::youtube::search_videos(search_str).await.unwrap();
-    let mut menu_options = vec![];
-    results[..5].iter().for_each(|x| {
-        if x.id.videoid.is_none() || x.id.videoid.is_none() {
-            return;
-        }
-        menu_options.push(CreateSelectMenuOption::new(
-            truncate(x.snippet.title.as_str(), 100),
-            x.id.videoid.clone().unwrap(),
-        ));
-    });
+    join(ctx, guild_id, channel_id).await;
+    let lines = queue::describe(&entries);
+    let positions = queue::enqueue(ctx, guild_id, entries).await;
 
-    CreateInteractionResponse::Message(
-        CreateInteractionResponseMessage::new().select_menu(
-            CreateSelectMenu::new(
-                "select_search",
-                CreateSelectMenuKind::String {
-                    options: menu_options,
-                },
-            )
-            .placeholder("Select a video"),
-        ),
-    )
+    let embed = if let Some(e) = queue::failed(&positions) {
+        CreateEmbed::new()
+            .color(Colour::new(COLOR_ERROR))
+            .title("Error")
+            .description(e)
+    } else {
+        CreateEmbed::new()
+            .color(Colour::new(COLOR_OK))
+            .title("Queued")
+            .description(queue::positions(&positions, lines))
+    };
+    interaction
+        .create_followup(
+            ctx,
+            CreateInteractionResponseFollowup::new().embed(embed.timestamp(Timestamp::now())),
+        )
+        .await?;
+    Ok(())
 }
@@ -0,0 +1,436 @@
+use log::warn;
+use rand::seq::SliceRandom;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use url::Url;
+
+use crate::{
+    COLOR_ERROR, COLOR_OK, UserData,
+    commands::play::TrackMeta,
+    playlists::PlaylistTrack,
+    queue::{self, QueueEntry},
+};
+
+/// Discord shows at most this many choices.
+const MAX_CHOICES: usize = 25;
+const MAX_LISTED: usize = 20;
+
+pub fn register() -> CreateCommand {
+    let name = |description: &str| {
+        CreateCommandOption::new(CommandOptionType::String, "name", description)
+            .required(true)
+            .set_autocomplete(true)
+    };
+    let subcommand = |name: &str, description: &str| {
+        CreateCommandOption::new(CommandOptionType::SubCommand, name, description)
+    };
+    CreateCommand::new("playlist")
+        .description("Save tracks to play again later")
+        .add_option(
+            subcommand("create", "Start a new playlist").add_sub_option(
+                CreateCommandOption::new(CommandOptionType::String, "name", "What to call it")
+                    .required(true),
+            ),
+        )
+        .add_option(
+            subcommand("add", "Add a track, the one playing if no link is given")
+                .add_sub_option(name("The playlist to add to"))
+                .add_sub_option(CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "link",
+                    "A link, or something to search for",
+                )),
+        )
+        .add_option(
+            subcommand("remove", "Take a track out")
+                .add_sub_option(name("The playlist to take it from"))
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::Integer,
+                        "position",
+                        "Its number in /playlist show",
+                    )
+                    .min_int_value(1)
+                    .required(true),
+                ),
+        )
+        .add_option(
+            subcommand("show", "List a playlist's tracks").add_sub_option(name("The playlist")),
+        )
+        .add_option(
+            subcommand("play", "Queue a whole playlist")
+                .add_sub_option(name("The playlist to play"))
+                .add_sub_option(CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "shuffle",
+                    "Queue it in a random order",
+                )),
+        )
+        .add_option(
+            subcommand("delete", "Delete a playlist")
+                .add_sub_option(name("The playlist to delete")),
+        )
+        .add_option(
+            subcommand("rename", "Rename a playlist")
+                .add_sub_option(name("The playlist to rename"))
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "new_name",
+                        "What to call it now",
+                    )
+                    .required(true),
+                ),
+        )
+        .add_option(
+            subcommand("share", "Let everyone here play a playlist")
+                .add_sub_option(name("The playlist to share"))
+                .add_sub_option(CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "shared",
+                    "Set to false to stop sharing it",
+                )),
+        )
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let Some(ResolvedOption {
+        name: subcommand,
+        value: ResolvedValue::SubCommand(options),
+        ..
+    }) = interaction.data.options().first().cloned()
+    else {
+        warn!("playlist interaction option not subcommand");
+        return Ok(());
+    };
+    let mut name = "";
+    let mut link = None;
+    let mut new_name = "";
+    let mut position = 0;
+    let mut shuffle = false;
+    let mut shared = true;
+    for option in &options {
+        match (option.name, &option.value) {
+            ("name", ResolvedValue::String(s)) => name = s,
+            ("link", ResolvedValue::String(s)) => link = Some(s.trim()),
+            ("new_name", ResolvedValue::String(s)) => new_name = s,
+            ("position", ResolvedValue::Integer(n)) => position = *n as usize,
+            ("shuffle", ResolvedValue::Boolean(b)) => shuffle = *b,
+            ("shared", ResolvedValue::Boolean(b)) => shared = *b,
+            _ => {}
+        }
+    }
+    let (name, new_name) = (name.trim(), new_name.trim());
+
+    let guild_id = interaction.guild_id.unwrap();
+    let user = interaction.user.id;
+    let playlists = {
+        let typemap = ctx.data.read().await;
+        typemap.get::<UserData>().unwrap().playlists.clone()
+    };
+
+    let result = match subcommand {
+        "create" => playlists
+            .create(user, name)
+            .map(|()| {
+                CreateEmbed::new()
+                    .title(format!("Created {name}"))
+                    .description(format!("Add tracks with `/playlist add {name}`"))
+            })
+            .map_err(|e| format!("{e:#}")),
+        "add" => {
+            // Looking up a link's title or searching can take a moment.
+            interaction.defer(ctx).await?;
+            let result = match track(ctx, guild_id, link).await {
+                Ok(track) => {
+                    let line = line(&track);
+                    playlists
+                        .add(user, name, track)
+                        .map(|n| {
+                            CreateEmbed::new()
+                                .title(format!("Added to {name}"))
+                                .description(format!("`#{n}` {line}"))
+                        })
+                        .map_err(|e| format!("{e:#}"))
+                }
+                Err(e) => Err(e),
+            };
+            return followup(ctx, interaction, result).await;
+        }
+        "remove" => playlists
+            .remove(user, name, position)
+            .map(|track| {
+                CreateEmbed::new()
+                    .title(format!("Removed from {name}"))
+                    .description(line(&track))
+            })
+            .map_err(|e| format!("{e:#}")),
+        "show" => match playlists.find(user, guild_id, name) {
+            Some((owner, name, playlist)) => {
+                let mut lines: Vec<String> = playlist
+                    .tracks
+                    .iter()
+                    .take(MAX_LISTED)
+                    .enumerate()
+                    .map(|(i, t)| format!("`#{}` {}", i + 1, line(t)))
+                    .collect();
+                if playlist.tracks.is_empty() {
+                    lines.push("Nothing in it yet".to_string());
+                } else if playlist.tracks.len() > MAX_LISTED {
+                    lines.push(format!(
+                        "...and {} more",
+                        playlist.tracks.len() - MAX_LISTED
+                    ));
+                }
+                let sharing = if playlist.shared_in.contains(&guild_id) {
+                    "shared here"
+                } else {
+                    "private"
+                };
+                Ok(CreateEmbed::new()
+                    .title(name)
+                    .description(format!("By <@{owner}>, {sharing}\n\n{}", lines.join("\n"))))
+            }
+            None => Err(format!("There's no playlist called {name} you can see")),
+        },
+        "play" => {
+            // Joining and starting the first track can take a moment.
+            interaction.defer(ctx).await?;
+            let result = play(ctx, interaction, name, shuffle).await;
+            return followup(ctx, interaction, result).await;
+        }
+        "delete" => playlists
+            .delete(user, name)
+            .map(|()| CreateEmbed::new().title(format!("Deleted {name}")))
+            .map_err(|e| format!("{e:#}")),
+        "rename" => playlists
+            .rename(user, name, new_name)
+            .map(|()| CreateEmbed::new().title(format!("Renamed {name} to {new_name}")))
+            .map_err(|e| format!("{e:#}")),
+        "share" => playlists
+            .share(user, name, guild_id, shared)
+            .map(|()| {
+                CreateEmbed::new().title(if shared {
+                    format!("Everyone here can play {name} now")
+                } else {
+                    format!("Only you can play {name} now")
+                })
+            })
+            .map_err(|e| format!("{e:#}")),
+        _ => return Ok(()),
+    };
+    respond(ctx, interaction, result).await
+}
+
+/// What to add: the link, the top search result for other text, or the
+/// current track when there's neither.
+async fn track(
+    ctx: &Context,
+    guild_id: GuildId,
+    link: Option<&str>,
+) -> Result<PlaylistTrack, String> {
+    let Some(link) = link.filter(|l| !l.is_empty()) else {
+        let typemap = ctx.data.read().await;
+        let data = typemap.get::<UserData>().unwrap();
+        let track = data
+            .track_handles
+            .get(&guild_id)
+            .ok_or("Nothing is playing, give a link")?;
+        let meta = track.data::<TrackMeta>();
+        return Ok(PlaylistTrack {
+            url: meta.url.to_string(),
+            title: meta.title.clone(),
+        });
+    };
+    if let Ok(url) = Url::parse(link) {
+        let allowed = {
+            let typemap = ctx.data.read().await;
+            let data = typemap.get::<UserData>().unwrap();
+            crate::source::allowed(&url, data.library.as_deref(), &data.attachments)
+        };
+        if !allowed {
+            return Err("Only http and https links can be saved".into());
+        }
+        let title = super::play::track_title(ctx, &url, String::new()).await;
+        return Ok(PlaylistTrack {
+            url: url.to_string(),
+            title,
+        });
+    }
+    let search = {
+        let typemap = ctx.data.read().await;
+        typemap.get::<UserData>().unwrap().search.clone()
+    };
+    match search.search(link, 1, None).await {
+        Ok(page) => page
+            .results
+            .into_iter()
+            .next()
+            .map(|r| PlaylistTrack {
+                url: format!("https://youtube.com/watch?v={}", r.id),
+                title: r.title,
+            })
+            .ok_or_else(|| format!("No results for `{link}`")),
+        Err(e) => Err(format!("Search failed: {e}")),
+    }
+}
+
+/// Queues the whole playlist in the caller's voice channel.
+async fn play(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    name: &str,
+    shuffle: bool,
+) -> Result<CreateEmbed, String> {
+    let guild_id = interaction.guild_id.unwrap();
+    let Some(channel_id) = ctx
+        .cache
+        .guild(guild_id)
+        .and_then(|g| g.voice_states.get(&interaction.user.id)?.channel_id)
+    else {
+        return Err("Join a voice channel first".into());
+    };
+
+    let typemap = ctx.data.read().await;
+    let data = typemap.get::<UserData>().unwrap();
+    let Some((_, name, playlist)) = data.playlists.find(interaction.user.id, guild_id, name) else {
+        return Err(format!("There's no playlist called {name} you can see"));
+    };
+    let mut entries: Vec<QueueEntry> = playlist
+        .tracks
+        .iter()
+        .filter_map(|t| {
+            Some(QueueEntry {
+                url: Url::parse(&t.url).ok()?,
+                title: t.title.clone(),
+                requested_in: interaction.channel_id,
+                requested_by: Some(interaction.user.id),
+            })
+        })
+        .collect();
+    if entries.is_empty() {
+        return Err(format!("{name} has no tracks yet"));
+    }
+    if shuffle {
+        entries.shuffle(&mut rand::rng());
+    }
+    let lines = queue::describe(&entries);
+
+    drop(typemap);
+    super::play::join(ctx, guild_id, channel_id).await;
+    let positions = queue::enqueue(ctx, guild_id, entries).await;
+    if let Some(e) = queue::failed(&positions) {
+        return Err(e);
+    }
+    Ok(CreateEmbed::new()
+        .title(format!("Queued {name}"))
+        .description(queue::positions(&positions, lines)))
+}
+
+/// Suggests playlists for `/playlist ... name`: the caller's own, and for
+/// `show` and `play` the ones shared in the guild too.
+pub async fn autocomplete(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+) -> Result<(), serenity::Error> {
+    let Some(focused) = interaction.data.autocomplete() else {
+        return Ok(());
+    };
+    let typed = focused.value.trim().to_lowercase();
+    let subcommand = interaction
+        .data
+        .options
+        .first()
+        .map(|o| o.name.as_str())
+        .unwrap_or_default();
+    let user = interaction.user.id;
+    let playlists = {
+        let typemap = ctx.data.read().await;
+        typemap.get::<UserData>().unwrap().playlists.clone()
+    };
+
+    // Label, name to match against and value.
+    let mut choices: Vec<(String, String, String)> = playlists
+        .names(user)
+        .into_iter()
+        .map(|n| (n.clone(), n.clone(), n))
+        .collect();
+    if let (Some(guild_id), "show" | "play") = (interaction.guild_id, subcommand) {
+        for (owner, name) in playlists.shared(guild_id, user) {
+            let by = ctx
+                .cache
+                .user(owner)
+                .map(|u| u.name.clone())
+                .unwrap_or_else(|| "someone".to_string());
+            // Qualified by owner, names only need to be unique per user.
+            choices.push((
+                format!("{name} (by {by})"),
+                name.clone(),
+                format!("{owner}:{name}"),
+            ));
+        }
+    }
+
+    let response = choices
+        .into_iter()
+        .filter(|(_, name, _)| name.to_lowercase().contains(&typed))
+        .take(MAX_CHOICES)
+        .fold(
+            CreateAutocompleteResponse::new(),
+            |response, (label, _, value)| {
+                response.add_string_choice(label.chars().take(100).collect::<String>(), value)
+            },
+        );
+    interaction
+        .create_response(ctx, CreateInteractionResponse::Autocomplete(response))
+        .await
+}
+
+/// A link to the track, or just its title for local files.
+fn line(track: &PlaylistTrack) -> String {
+    match Url::parse(&track.url) {
+        Ok(url) if url.scheme() != "file" => format!("[{}]({url})", track.title),
+        _ => track.title.clone(),
+    }
+}
+
+fn embed(result: Result<CreateEmbed, String>) -> CreateEmbed {
+    match result {
+        Ok(embed) => embed.color(Colour::new(COLOR_OK)),
+        Err(e) => CreateEmbed::new()
+            .color(Colour::new(COLOR_ERROR))
+            .description(e)
+            .title("Error"),
+    }
+    .timestamp(Timestamp::now())
+}
+
+async fn respond(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    result: Result<CreateEmbed, String>,
+) -> Result<(), serenity::Error> {
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().embed(embed(result)),
+            ),
+        )
+        .await
+}
+
+async fn followup(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    result: Result<CreateEmbed, String>,
+) -> Result<(), serenity::Error> {
+    interaction
+        .create_followup(
+            ctx,
+            CreateInteractionResponseFollowup::new().embed(embed(result)),
+        )
+        .await?;
+    Ok(())
+}
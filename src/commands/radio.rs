@@ -0,0 +1,130 @@
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{COLOR_ERROR, COLOR_OK, UserData, queue, radio::Radio};
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("radio")
+        .description("Keep playing similar tracks once the queue runs out")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "query",
+            "What to start from, otherwise whatever played last",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "enabled",
+            "Set to false to turn the radio off",
+        ))
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let mut seed = None;
+    let mut enabled = true;
+    for option in interaction.data.options() {
+        match (option.name, option.value) {
+            ("query", ResolvedValue::String(query)) => seed = Some(query.trim().to_string()),
+            ("enabled", ResolvedValue::Boolean(on)) => enabled = on,
+            _ => {}
+        }
+    }
+    // Picking the first track can mean a search.
+    interaction.defer(ctx).await?;
+
+    let embed = match start(ctx, interaction, seed.filter(|s| !s.is_empty()), enabled).await {
+        Ok(description) => CreateEmbed::new()
+            .color(Colour::new(COLOR_OK))
+            .title("Radio")
+            .description(description),
+        Err(e) => CreateEmbed::new()
+            .color(Colour::new(COLOR_ERROR))
+            .title("Error")
+            .description(e),
+    };
+    interaction
+        .create_followup(
+            ctx,
+            CreateInteractionResponseFollowup::new().embed(embed.timestamp(Timestamp::now())),
+        )
+        .await?;
+    Ok(())
+}
+
+async fn start(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    seed: Option<String>,
+    enabled: bool,
+) -> Result<String, String> {
+    let guild_id = interaction.guild_id.unwrap();
+    if !enabled {
+        let mut typemap = ctx.data.write().await;
+        let data = typemap.get_mut::<UserData>().unwrap();
+        data.radios.remove(&guild_id);
+        return Ok("The radio is off".into());
+    }
+    let channel_id = ctx
+        .cache
+        .guild(guild_id)
+        .and_then(|g| g.voice_states.get(&interaction.user.id)?.channel_id);
+
+    let in_call = {
+        let typemap = ctx.data.read().await;
+        typemap
+            .get::<UserData>()
+            .unwrap()
+            .songbird
+            .get(guild_id)
+            .is_some()
+    };
+    match (in_call, channel_id) {
+        (true, _) => {}
+        (false, Some(channel_id)) => {
+            super::play::join(ctx, guild_id, channel_id).await;
+        }
+        (false, None) => return Err("Join a voice channel first".into()),
+    }
+    let playing = {
+        let mut typemap = ctx.data.write().await;
+        let data = typemap.get_mut::<UserData>().unwrap();
+        data.radios.insert(
+            guild_id,
+            Radio {
+                seed: seed.clone(),
+                requested_in: interaction.channel_id,
+            },
+        );
+        match data.track_handles.get(&guild_id) {
+            Some(track) => {
+                // A looping track would never make way for the radio.
+                let _ = track.disable_loop();
+                track.get_info().await.is_ok_and(|i| !i.playing.is_done())
+            }
+            None => false,
+        }
+    };
+    let about = match &seed {
+        Some(seed) => format!("tracks like **{seed}**"),
+        None => "tracks like what played last".to_string(),
+    };
+    if playing {
+        return Ok(format!("Playing {about} once the queue runs out"));
+    }
+
+    let Some(entry) = crate::radio::pick(ctx, guild_id).await else {
+        let mut typemap = ctx.data.write().await;
+        let data = typemap.get_mut::<UserData>().unwrap();
+        data.radios.remove(&guild_id);
+        return Err("Nothing to go on yet, give the radio a query".into());
+    };
+    let line = queue::describe(std::slice::from_ref(&entry)).concat();
+    let positions = queue::enqueue(ctx, guild_id, vec![entry]).await;
+    if let Some(e) = queue::failed(&positions) {
+        return Err(e);
+    }
+    Ok(format!(
+        "Playing {about}\n{}",
+        queue::positions(&positions, vec![line])
+    ))
+}
@@ -1,16 +1,23 @@
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering},
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
+use anyhow::Context as _;
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
+use serde::Serialize;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 use serenity::{async_trait, builder::*};
 use songbird::{
-    CoreEvent, Event, EventContext, EventHandler, TrackEvent,
+    CoreEvent, Event, EventContext, EventHandler,
     model::{
         id::UserId,
         payload::{ClientDisconnect, Speaking},
@@ -18,7 +25,6 @@ use songbird::{
     packet::Packet,
 };
 
-use super::TrackErrorNotifier;
 use crate::{COLOR_ERROR, COLOR_OK, UserData};
 
 const WAV_SPEC: hound::WavSpec = hound::WavSpec {
@@ -28,13 +34,27 @@ const WAV_SPEC: hound::WavSpec = hound::WavSpec {
     sample_format: hound::SampleFormat::Int,
 };
 const SAMPLES_20MS: usize = (0.02 * 48_000.0) as usize;
+const RECORDINGS_DIR: &str = "recordings";
+/// How often the auto-stop watchdog re-checks the channel when nobody leaves.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
 
 pub fn register() -> CreateCommand {
-    CreateCommand::new("record").description("Record bot audio to a multi-track flac file.")
+    CreateCommand::new("record")
+        .description("Record everyone in the voice channel to one file per speaker")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "start",
+            "Join your voice channel and start recording",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "stop",
+            "Stop recording and upload the session",
+        ))
 }
 
 #[derive(Clone)]
-struct Receiver {
+pub struct Receiver {
     inner: Arc<InnerReceiver>,
 }
 
@@ -44,10 +64,17 @@ struct InnerReceiver {
     known_ssrcs: DashMap<u32, UserId>,
     writers: DashMap<u32, hound::WavWriter<std::io::BufWriter<std::fs::File>>>,
     start_time: DateTime<Utc>,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    last_voice: Mutex<Instant>,
+    /// Set by [`Receiver::finish`], after which no new WAV files get created.
+    finished: Mutex<bool>,
+    /// Pokes the auto-stop watchdog when someone leaves the call.
+    wake: tokio::sync::Notify,
 }
 
 impl Receiver {
-    pub fn new() -> Self {
+    pub fn new(guild_id: GuildId, channel_id: ChannelId) -> Self {
         // You can manage state here, such as a buffer of audio packet bytes so
         // you can later store them in intervals.
         Self {
@@ -57,6 +84,11 @@ impl Receiver {
                 known_ssrcs: DashMap::new(),
                 writers: DashMap::new(),
                 start_time: chrono::offset::Utc::now(),
+                guild_id,
+                channel_id,
+                last_voice: Mutex::new(Instant::now()),
+                finished: Mutex::new(false),
+                wake: tokio::sync::Notify::new(),
             }),
         }
     }
@@ -66,7 +98,10 @@ impl Receiver {
     }
 
     pub fn start_time_formatted(&self) -> String {
-        self.inner.start_time.format("%Y-%m-%d_%H-%M").to_string()
+        self.inner
+            .start_time
+            .format("%Y-%m-%d_%H-%M-%S")
+            .to_string()
     }
 
     pub fn set_record(&self, v: bool) {
@@ -76,14 +111,180 @@ impl Receiver {
     pub fn get_record(&self) -> bool {
         self.inner.record.load(Ordering::Relaxed)
     }
+
+    fn is_same(&self, other: &Receiver) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Why this session should end on its own, if it should.
+    fn stop_reason(
+        &self,
+        ctx: &Context,
+        silence_timeout: Option<Duration>,
+    ) -> Option<&'static str> {
+        let listeners = ctx
+            .cache
+            .guild(self.inner.guild_id)
+            .map(|g| {
+                g.voice_states
+                    .values()
+                    .filter(|vs| vs.channel_id == Some(self.inner.channel_id))
+                    .filter(|vs| {
+                        !vs.member
+                            .as_ref()
+                            .map(|m| m.user.bot)
+                            .or_else(|| ctx.cache.user(vs.user_id).map(|u| u.bot))
+                            .unwrap_or(false)
+                    })
+                    .count()
+            })
+            .unwrap_or(0);
+        if listeners == 0 {
+            return Some("everyone left");
+        }
+
+        if let Some(timeout) = silence_timeout
+            && self.inner.last_voice.lock().unwrap().elapsed() >= timeout
+        {
+            return Some("nobody has spoken for a while");
+        }
+
+        None
+    }
+
+    fn dir(&self) -> PathBuf {
+        Path::new(RECORDINGS_DIR)
+            .join(self.inner.guild_id.to_string())
+            .join(self.start_time_formatted())
+    }
+
+    /// The WAV file for `ssrc`, created on first use. `None` once the session
+    /// is finished or the file could not be created.
+    fn writer(
+        &self,
+        ssrc: u32,
+    ) -> Option<
+        dashmap::mapref::one::RefMut<'_, u32, hound::WavWriter<std::io::BufWriter<std::fs::File>>>,
+    > {
+        // Held while creating, so `finish` can't close the session in between
+        // and have the file truncated again.
+        let finished = self.inner.finished.lock().unwrap();
+        if *finished {
+            return None;
+        }
+        match self.inner.writers.entry(ssrc) {
+            dashmap::Entry::Occupied(e) => Some(e.into_ref()),
+            dashmap::Entry::Vacant(e) => {
+                let dir = self.dir();
+                let p = dir.join(format!("{ssrc}.wav"));
+                info!("{}", p.display());
+                let writer = std::fs::create_dir_all(&dir)
+                    .and_then(|_| hound::WavWriter::create(&p, WAV_SPEC).map_err(io_error));
+                match writer {
+                    Ok(writer) => Some(e.insert(writer)),
+                    Err(err) => {
+                        error!("could not create {}: {err}", p.display());
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stops recording and closes every WAV file, returning what was written.
+    pub fn finish(&self) -> anyhow::Result<Recording> {
+        {
+            let mut finished = self.inner.finished.lock().unwrap();
+            *finished = true;
+            self.set_record(false);
+        }
+
+        let ssrcs: Vec<u32> = self.inner.writers.iter().map(|w| *w.key()).collect();
+        let mut tracks = vec![];
+        for ssrc in ssrcs {
+            if let Some((_, writer)) = self.inner.writers.remove(&ssrc) {
+                writer.finalize()?;
+                tracks.push(RecordedTrack {
+                    ssrc,
+                    user_id: self.inner.known_ssrcs.get(&ssrc).map(|u| u.0),
+                    user_name: None,
+                    file: format!("{ssrc}.wav"),
+                });
+            }
+        }
+
+        Ok(Recording {
+            dir: self.dir(),
+            started_at: self.start_time(),
+            ended_at: Utc::now(),
+            tracks,
+        })
+    }
+}
+
+fn io_error(e: hound::Error) -> std::io::Error {
+    match e {
+        hound::Error::IoError(e) => e,
+        e => std::io::Error::other(e),
+    }
+}
+
+/// Manifest written alongside the WAV files of a finished session.
+#[derive(Serialize)]
+pub struct Recording {
+    #[serde(skip)]
+    pub dir: PathBuf,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub tracks: Vec<RecordedTrack>,
+}
+
+#[derive(Serialize)]
+pub struct RecordedTrack {
+    pub ssrc: u32,
+    pub user_id: Option<u64>,
+    pub user_name: Option<String>,
+    pub file: String,
+}
+
+impl Recording {
+    /// Writes `manifest.json` and zips it up with the WAV files next to the
+    /// session directory.
+    pub fn package(&self) -> anyhow::Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)?;
+        let manifest = serde_json::to_vec_pretty(self)?;
+        std::fs::write(self.dir.join("manifest.json"), &manifest)?;
+
+        let zip_path = self.dir.with_extension("zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path)?);
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .large_file(true);
+
+        zip.start_file("manifest.json", options)?;
+        zip.write_all(&manifest)?;
+        for track in &self.tracks {
+            let path = self.dir.join(&track.file);
+            zip.start_file(&track.file, options)?;
+            std::io::copy(
+                &mut std::fs::File::open(&path)
+                    .with_context(|| format!("opening {}", path.display()))?,
+                &mut zip,
+            )?;
+        }
+        zip.finish()?;
+
+        Ok(zip_path)
+    }
 }
 
 #[async_trait]
 impl EventHandler for Receiver {
     #[allow(unused_variables)]
     async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
-        if !self.inner.record.load(Ordering::Relaxed) {
-            return None;
+        if !self.get_record() {
+            // Unregisters this handler from the call.
+            return Some(Event::Cancel);
         }
         use EventContext as Ctx;
         match ctx {
@@ -126,8 +327,9 @@ impl EventHandler for Receiver {
                     self.inner
                         .last_tick_was_empty
                         .store(false, Ordering::SeqCst);
+                    *self.inner.last_voice.lock().unwrap() = Instant::now();
 
-                    info!("Voice tick ({speaking}/{total_participants} live):");
+                    debug!("Voice tick ({speaking}/{total_participants} live):");
 
                     // You can also examine tick.silent to see users who are present
                     // but haven't spoken in this tick.
@@ -150,51 +352,39 @@ impl EventHandler for Receiver {
 
                             if let Some(packet) = &data.packet {
                                 let rtp = packet.rtp();
-                                info!(
+                                debug!(
                                     "\t{ssrc}/{user_id_str}: packet seq {} ts {} -- {audio_str}",
                                     rtp.get_sequence().0,
                                     rtp.get_timestamp().0
                                 );
                             } else {
-                                info!("\t{ssrc}/{user_id_str}: Missed packet -- {audio_str}");
+                                debug!("\t{ssrc}/{user_id_str}: Missed packet -- {audio_str}");
                             }
 
                             // 20ms of 16-bit little endian 48khz 2 channel PCM
-                            if let Some(mut writer) = self.inner.writers.get_mut(ssrc) {
-                                for s in decoded_voice {
-                                    writer.write_sample(*s).unwrap()
-                                }
-                            } else {
-                                let t = self.start_time_formatted();
-                                let p = format!("recordings/{t}/{ssrc}.wav");
-                                info!("{}", p);
-                                std::fs::create_dir_all(t).unwrap();
-                                let mut writer = hound::WavWriter::create(p, WAV_SPEC).unwrap();
-                                for s in decoded_voice {
-                                    writer.write_sample(*s).unwrap()
+                            let Some(mut writer) = self.writer(*ssrc) else {
+                                continue;
+                            };
+                            for s in decoded_voice {
+                                if let Err(e) = writer.write_sample(*s) {
+                                    error!("could not write audio of {ssrc}: {e}");
+                                    break;
                                 }
-                                self.inner.writers.insert(*ssrc, writer);
                             }
                         } else {
-                            info!("\t{ssrc}/{user_id_str}: Decode disabled.");
+                            debug!("\t{ssrc}/{user_id_str}: Decode disabled.");
                         }
                     }
                 }
                 for ssrc in &tick.silent {
-                    if let Some(mut writer) = self.inner.writers.get_mut(ssrc) {
-                        for _ in 0..SAMPLES_20MS {
-                            writer.write_sample(0).unwrap();
+                    let Some(mut writer) = self.writer(*ssrc) else {
+                        continue;
+                    };
+                    for _ in 0..SAMPLES_20MS {
+                        if let Err(e) = writer.write_sample(0) {
+                            error!("could not write silence of {ssrc}: {e}");
+                            break;
                         }
-                    } else {
-                        let t = self.start_time_formatted();
-                        let p = format!("recordings/{t}/{ssrc}.wav");
-                        info!("{}", p);
-                        std::fs::create_dir_all(t).unwrap();
-                        let mut writer = hound::WavWriter::create(p, WAV_SPEC).unwrap();
-                        for _ in 0..SAMPLES_20MS {
-                            writer.write_sample(0).unwrap();
-                        }
-                        self.inner.writers.insert(*ssrc, writer);
                     }
                 }
             }
@@ -202,7 +392,7 @@ impl EventHandler for Receiver {
                 // An event which fires for every received audio packet,
                 // containing the decoded data.
                 let rtp = packet.rtp();
-                info!(
+                debug!(
                     "Received voice packet from SSRC {}, sequence {}, timestamp {} -- {}B long",
                     rtp.get_ssrc(),
                     rtp.get_sequence().0,
@@ -213,7 +403,7 @@ impl EventHandler for Receiver {
             Ctx::RtcpPacket(data) => {
                 // An event which fires for every received rtcp packet,
                 // containing the call statistics and reporting information.
-                info!("RTCP packet received: {:?}", data.packet);
+                debug!("RTCP packet received: {:?}", data.packet);
             }
             Ctx::ClientDisconnect(ClientDisconnect { user_id, .. }) => {
                 // You can implement your own logic here to handle a user who has left the
@@ -222,6 +412,7 @@ impl EventHandler for Receiver {
                 // first speaking.
 
                 info!("Client disconnected: user {:?}", user_id);
+                self.inner.wake.notify_one();
             }
             _ => {
                 // We won't be registering this struct for any more event classes.
@@ -261,14 +452,17 @@ pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(),
         return Ok(());
     }
 
-    // let guild_id = interaction.guild_id.unwrap();
-    // let mut typemap = ctx.data.write().await;
-    // let data = typemap.get_mut::<UserData>().unwrap();
-    // let track = data.track_handles.get_mut(&guild_id).unwrap();
-
-    // let _ = track.stop();
-    // let _ = data.songbird.remove(guild_id).await;
+    match interaction.data.options().first().map(|o| o.name) {
+        Some("start") => start(ctx, interaction).await,
+        Some("stop") => stop(ctx, interaction).await,
+        _ => {
+            warn!("record interaction option not subcommand");
+            Ok(())
+        }
+    }
+}
 
+async fn start(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
     let (guild_id, channel_id) = {
         let guild_id = interaction.guild_id.unwrap();
         let user = interaction.user.id;
@@ -301,66 +495,90 @@ pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(),
 
     let mut typemap = ctx.data.write().await;
     let data = typemap.get_mut::<UserData>().unwrap();
-    let manager = &data.songbird;
-
-    let call = manager.join(guild_id, channel_id).await;
-    if let Ok(handler_lock) = call {
-        let mut handler = handler_lock.lock().await;
-        handler.remove_all_global_events();
 
-        handler.add_global_event(TrackEvent::Error.into(), TrackErrorNotifier);
-        let _ = handler.deafen(false).await;
-
-        let evt_receiver = Receiver::new();
-        if data.is_recording {
-            evt_receiver.set_record(false);
-            data.is_recording = false;
-        } else {
-            evt_receiver.set_record(true);
-            data.is_recording = true;
-        }
-
-        handler.add_global_event(CoreEvent::SpeakingStateUpdate.into(), evt_receiver.clone());
-        handler.add_global_event(CoreEvent::RtpPacket.into(), evt_receiver.clone());
-        handler.add_global_event(CoreEvent::RtcpPacket.into(), evt_receiver.clone());
-        handler.add_global_event(CoreEvent::ClientDisconnect.into(), evt_receiver.clone());
-        handler.add_global_event(CoreEvent::VoiceTick.into(), evt_receiver);
-    } else if let Err(e) = call {
-        warn!("{}", e);
-    }
-
-    // if let Ok(handler_lock) = manager.join(guild_id, channel_id).await {
-    if manager.join(guild_id, channel_id).await.is_ok() {
-        // check_msg(
-        //     msg.channel_id
-        //         .say(&ctx.http, &format!("Joined {}", channel_id.mention()))
-        //         .await,
-        // );
+    if data.recordings.contains_key(&guild_id) {
         interaction
             .create_response(
                 ctx,
                 CreateInteractionResponse::Message(
                     CreateInteractionResponseMessage::new().embed(
                         CreateEmbed::new()
-                            .color(Colour::new(COLOR_OK))
-                            .title(format!(
-                                "Recording {}",
-                                if data.is_recording { "On" } else { "Off" }
-                            ))
+                            .color(Colour::new(COLOR_ERROR))
+                            .description("Already recording, use `/record stop` first")
+                            .title("Error")
                             .timestamp(Timestamp::now()),
                     ),
                 ),
             )
             .await?;
-    } else {
-        // Although we failed to join, we need to clear out existing event handlers on the call.
-        _ = manager.remove(guild_id).await;
-
-        // check_msg(
-        //     msg.channel_id
-        //         .say(&ctx.http, "Error joining the channel")
-        //         .await,
-        // );
+        return Ok(());
+    }
+
+    let manager = &data.songbird;
+
+    match manager.join(guild_id, channel_id).await {
+        Ok(handler_lock) => {
+            let mut handler = handler_lock.lock().await;
+            let _ = handler.deafen(false).await;
+
+            // Handlers from a previous session cancel themselves once their
+            // receiver stops recording, so these are the only live ones.
+            let evt_receiver = Receiver::new(guild_id, channel_id);
+            handler.add_global_event(CoreEvent::SpeakingStateUpdate.into(), evt_receiver.clone());
+            handler.add_global_event(CoreEvent::RtpPacket.into(), evt_receiver.clone());
+            handler.add_global_event(CoreEvent::RtcpPacket.into(), evt_receiver.clone());
+            handler.add_global_event(CoreEvent::ClientDisconnect.into(), evt_receiver.clone());
+            handler.add_global_event(CoreEvent::VoiceTick.into(), evt_receiver.clone());
+            data.recordings.insert(guild_id, evt_receiver.clone());
+            spawn_watchdog(ctx.clone(), evt_receiver, interaction.channel_id);
+
+            interaction
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new().embed(
+                            CreateEmbed::new()
+                                .color(Colour::new(COLOR_OK))
+                                .title("Recording On")
+                                .timestamp(Timestamp::now()),
+                        ),
+                    ),
+                )
+                .await?;
+        }
+        Err(e) => {
+            warn!("{}", e);
+            // Although we failed to join, we need to clear out existing event handlers on the call.
+            _ = manager.remove(guild_id).await;
+
+            interaction
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new().embed(
+                            CreateEmbed::new()
+                                .color(Colour::new(COLOR_ERROR))
+                                .title("Error joining")
+                                .timestamp(Timestamp::now()),
+                        ),
+                    ),
+                )
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn stop(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let guild_id = interaction.guild_id.unwrap();
+    let receiver = {
+        let mut typemap = ctx.data.write().await;
+        let data = typemap.get_mut::<UserData>().unwrap();
+        data.recordings.remove(&guild_id)
+    };
+
+    let Some(receiver) = receiver else {
         interaction
             .create_response(
                 ctx,
@@ -368,27 +586,192 @@ pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(),
                     CreateInteractionResponseMessage::new().embed(
                         CreateEmbed::new()
                             .color(Colour::new(COLOR_ERROR))
-                            .title("Error joining")
+                            .description("Not recording")
+                            .title("Error")
                             .timestamp(Timestamp::now()),
                     ),
                 ),
             )
             .await?;
-    }
+        return Ok(());
+    };
 
-    // interaction
-    //     .create_response(
-    //         ctx,
-    //         CreateInteractionResponse::Message(
-    //             CreateInteractionResponseMessage::new().embed(
-    //                 CreateEmbed::new()
-    //                     .color(Colour::new(COLOR_OK))
-    //                     .title("a")
-    //                     .timestamp(Timestamp::now()),
-    //             ),
-    //         ),
-    //     )
-    //     .await?;
+    // Zipping and uploading can easily take longer than the 3s Discord gives us.
+    interaction.defer(ctx).await?;
+
+    let (embed, file) = finish_message(ctx, guild_id, receiver, "Recording Off").await;
+    interaction
+        .create_followup(
+            ctx,
+            CreateInteractionResponseFollowup::new()
+                .embed(embed)
+                .add_files(file),
+        )
+        .await?;
 
     Ok(())
 }
+
+/// Finalizes the recording by itself once every non-bot user has left the
+/// channel, or nobody has spoken for `RECORD_SILENCE_TIMEOUT_SECS` (default
+/// 5 minutes, 0 to disable). The result is posted to `text_channel`.
+fn spawn_watchdog(ctx: Context, receiver: Receiver, text_channel: ChannelId) {
+    let silence_timeout = env_duration("RECORD_SILENCE_TIMEOUT_SECS", 300);
+    let grace = env_duration("RECORD_GRACE_SECS", 10).unwrap_or_default();
+    let guild_id = receiver.inner.guild_id;
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = receiver.inner.wake.notified() => {}
+                _ = tokio::time::sleep(WATCHDOG_INTERVAL) => {}
+            }
+            if !receiver.get_record() {
+                return;
+            }
+            let Some(reason) = receiver.stop_reason(&ctx, silence_timeout) else {
+                continue;
+            };
+
+            // Give people a moment to rejoin or start talking again.
+            info!("Recording in {guild_id} will stop: {reason}");
+            tokio::time::sleep(grace).await;
+            if !receiver.get_record() {
+                return;
+            }
+            if receiver.stop_reason(&ctx, silence_timeout).is_none() {
+                continue;
+            }
+
+            let receiver = {
+                let mut typemap = ctx.data.write().await;
+                let data = typemap.get_mut::<UserData>().unwrap();
+                match data.recordings.get(&guild_id) {
+                    Some(r) if r.is_same(&receiver) => data.recordings.remove(&guild_id),
+                    // `/record stop` got there first.
+                    _ => None,
+                }
+            };
+            let Some(receiver) = receiver else {
+                return;
+            };
+
+            let (embed, file) = finish_message(
+                &ctx,
+                guild_id,
+                receiver,
+                &format!("Recording Off ({reason})"),
+            )
+            .await;
+            if let Err(e) = text_channel
+                .send_message(&ctx, CreateMessage::new().embed(embed).add_files(file))
+                .await
+            {
+                error!("failed to post recording: {e:?}");
+            }
+            return;
+        }
+    });
+}
+
+fn env_duration(key: &str, default_secs: u64) -> Option<Duration> {
+    let secs = std::env::var(key)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(default_secs);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Runs [`finish`] and describes the outcome for the channel.
+async fn finish_message(
+    ctx: &Context,
+    guild_id: GuildId,
+    receiver: Receiver,
+    title: &str,
+) -> (CreateEmbed, Option<CreateAttachment>) {
+    let embed = CreateEmbed::new().title(title).timestamp(Timestamp::now());
+    let result = match finish(ctx, guild_id, receiver).await {
+        Ok(Upload::Attachment(path)) => CreateAttachment::path(path)
+            .await
+            .map(|file| (embed.clone().color(Colour::new(COLOR_OK)), Some(file)))
+            .map_err(anyhow::Error::from),
+        Ok(Upload::Link(url)) => Ok((
+            embed
+                .clone()
+                .color(Colour::new(COLOR_OK))
+                .description(format!(
+                    "The recording is too large to attach, [download it here]({url})"
+                )),
+            None,
+        )),
+        Err(e) => Err(e),
+    };
+
+    result.unwrap_or_else(|e| {
+        error!("failed to save recording: {e:?}");
+        (
+            embed
+                .color(Colour::new(COLOR_ERROR))
+                .description(format!("Could not upload the recording: {e}")),
+            None,
+        )
+    })
+}
+
+/// Where a finished recording ended up.
+pub enum Upload {
+    /// Small enough to attach to a Discord message.
+    Attachment(PathBuf),
+    /// Uploaded to the configured bucket, reachable through this link.
+    Link(url::Url),
+}
+
+/// Closes the session's files, zips them with a manifest and picks an upload
+/// target based on the guild's attachment size limit.
+pub async fn finish(
+    ctx: &Context,
+    guild_id: GuildId,
+    receiver: Receiver,
+) -> anyhow::Result<Upload> {
+    let mut recording = tokio::task::spawn_blocking(move || receiver.finish()).await??;
+    for track in &mut recording.tracks {
+        track.user_name = track
+            .user_id
+            .and_then(|id| ctx.cache.user(id).map(|u| u.name.clone()));
+    }
+    let (zip_path, recording) =
+        tokio::task::spawn_blocking(move || recording.package().map(|p| (p, recording))).await??;
+
+    let size = tokio::fs::metadata(&zip_path).await?.len();
+    if size <= upload_limit(ctx, guild_id) {
+        return Ok(Upload::Attachment(zip_path));
+    }
+
+    let storage = {
+        let typemap = ctx.data.read().await;
+        typemap.get::<UserData>().unwrap().storage.clone()
+    };
+    let Some(storage) = storage else {
+        anyhow::bail!(
+            "the zip is {} MiB, too large to attach, and no S3 storage is configured",
+            size / 1024 / 1024
+        );
+    };
+
+    let key = format!(
+        "{RECORDINGS_DIR}/{guild_id}/{}.zip",
+        recording.started_at.format("%Y-%m-%d_%H-%M-%S")
+    );
+    storage.upload(&key, &zip_path, "application/zip").await?;
+    Ok(Upload::Link(storage.presigned_url(&key)))
+}
+
+/// Discord's attachment size limit, which depends on the guild's boost level.
+fn upload_limit(ctx: &Context, guild_id: GuildId) -> u64 {
+    const MIB: u64 = 1024 * 1024;
+    match ctx.cache.guild(guild_id).map(|g| g.premium_tier) {
+        Some(PremiumTier::Tier2) => 50 * MIB,
+        Some(PremiumTier::Tier3) => 100 * MIB,
+        _ => 10 * MIB,
+    }
+}
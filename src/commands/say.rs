@@ -0,0 +1,82 @@
+use log::warn;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{COLOR_ERROR, COLOR_OK, UserData};
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("say")
+        .description("Say something in the voice channel")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "text", "What to say")
+                .required(true),
+        )
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let Some(ResolvedOption {
+        value: ResolvedValue::String(text),
+        ..
+    }) = interaction.data.options().first().cloned()
+    else {
+        warn!("say interaction option not string");
+        return Ok(());
+    };
+    // Synthesizing takes a moment.
+    interaction.defer(ctx).await?;
+
+    let embed = match say(ctx, interaction, text).await {
+        Ok(()) => CreateEmbed::new()
+            .color(Colour::new(COLOR_OK))
+            .title("Saying")
+            .description(text),
+        Err(e) => CreateEmbed::new()
+            .color(Colour::new(COLOR_ERROR))
+            .description(e)
+            .title("Error"),
+    };
+    interaction
+        .create_followup(
+            ctx,
+            CreateInteractionResponseFollowup::new().embed(embed.timestamp(Timestamp::now())),
+        )
+        .await?;
+    Ok(())
+}
+
+async fn say(ctx: &Context, interaction: &CommandInteraction, text: &str) -> Result<(), String> {
+    let guild_id = interaction.guild_id.unwrap();
+    let channel_id = ctx
+        .cache
+        .guild(guild_id)
+        .and_then(|g| g.voice_states.get(&interaction.user.id)?.channel_id);
+
+    let (tts, call, volume, voice) = {
+        let typemap = ctx.data.read().await;
+        let data = typemap.get::<UserData>().unwrap();
+        (
+            data.tts.clone(),
+            data.songbird.get(guild_id),
+            data.volume(guild_id),
+            data.settings.get(guild_id).tts_voice,
+        )
+    };
+    // Before joining, so someone who has to wait doesn't pull the bot in.
+    if let Err(wait) = tts.allow(interaction.user.id) {
+        return Err(format!("You can use this again in {}s", wait.as_secs() + 1));
+    }
+    let call = match (call, channel_id) {
+        (Some(call), _) => call,
+        (None, Some(channel_id)) => super::play::join(ctx, guild_id, channel_id)
+            .await
+            .ok_or("Could not join your voice channel")?,
+        (None, None) => return Err("Join a voice channel first".into()),
+    };
+    let speech = tts
+        .synthesize(text, voice.as_deref())
+        .await
+        .map_err(|e| format!("{e:#}"))?;
+    crate::tts::speak(call, volume, speech).await;
+    Ok(())
+}
@@ -1,7 +1,12 @@
+use std::time::Duration;
+
+use log::{error, warn};
 use serenity::builder::*;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 
+use crate::{COLOR_ERROR, COLOR_OK, UserData};
+
 pub fn register() -> CreateCommand {
     CreateCommand::new("search")
         .description("Search for a song")
@@ -11,7 +16,8 @@ pub fn register() -> CreateCommand {
                 "query",
                 "Search for a song on YouTube",
             )
-            .required(true),
+            .required(true)
+            .set_autocomplete(true),
         )
 }
 
@@ -21,8 +27,202 @@ pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(),
         ..
     }) = interaction.data.options().first().cloned()
     {
-        let resp = super::play::search(search_str).await;
+        let resp = search(ctx, search_str).await;
         interaction.create_response(&ctx.http, resp).await?;
     }
     Ok(())
 }
+
+/// How long the buttons and menu of a search keep working.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const PAGE_SIZE: usize = 5;
+/// Discord's limit on `custom_id` length.
+const MAX_CUSTOM_ID: usize = 100;
+
+/// Which way a page button goes, kept in its `custom_id` as
+/// `search_page:<started>:<prev|next>:<token>:<query>`. The query comes last
+/// since it may itself contain `:`.
+pub const PAGE_PREFIX: &str = "search_page:";
+/// The result menu's `custom_id` is `select_search:<started>`.
+pub const SELECT_PREFIX: &str = "select_search";
+
+/// Starts a search session on its first page.
+pub async fn search(ctx: &Context, query: &str) -> CreateInteractionResponse {
+    let started = Timestamp::now().unix_timestamp();
+    match page(ctx, query, None, started).await {
+        Ok(message) => CreateInteractionResponse::Message(message),
+        Err(e) => CreateInteractionResponse::Message(error_message(e)),
+    }
+}
+
+/// Handles the previous/next buttons of a search.
+pub async fn run_component(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+) -> Result<(), serenity::Error> {
+    if is_expired(&interaction.data.custom_id) {
+        interaction.create_response(ctx, expired()).await?;
+        return Ok(());
+    }
+
+    let Some((started, _, token, query)) = interaction
+        .data
+        .custom_id
+        .strip_prefix(PAGE_PREFIX)
+        .and_then(|s| {
+            let mut parts = s.splitn(4, ':');
+            Some((
+                parts.next()?.parse::<i64>().ok()?,
+                parts.next()?,
+                parts.next()?,
+                parts.next()?,
+            ))
+        })
+    else {
+        warn!("malformed search page id {}", interaction.data.custom_id);
+        return Ok(());
+    };
+
+    let message = match page(ctx, query, Some(token), started).await {
+        Ok(message) => message,
+        Err(e) => error_message(e).components(vec![]),
+    };
+    interaction
+        .create_response(ctx, CreateInteractionResponse::UpdateMessage(message))
+        .await?;
+    Ok(())
+}
+
+/// Whether the search a component belongs to has timed out. Ids without a
+/// start time predate sessions and count as expired too.
+pub fn is_expired(custom_id: &str) -> bool {
+    let started = custom_id
+        .strip_prefix(PAGE_PREFIX)
+        .or_else(|| custom_id.strip_prefix(SELECT_PREFIX)?.strip_prefix(':'))
+        .and_then(|s| s.split(':').next()?.parse::<i64>().ok());
+    match started {
+        Some(started) => {
+            Timestamp::now().unix_timestamp() - started > SESSION_TIMEOUT.as_secs() as i64
+        }
+        None => true,
+    }
+}
+
+/// Replaces an expired search with a notice, removing its components.
+pub fn expired() -> CreateInteractionResponse {
+    CreateInteractionResponse::UpdateMessage(
+        error_message("This search has expired, search again".to_string()).components(vec![]),
+    )
+}
+
+async fn page(
+    ctx: &Context,
+    query: &str,
+    token: Option<&str>,
+    started: i64,
+) -> Result<CreateInteractionResponseMessage, String> {
+    let provider = {
+        let typemap = ctx.data.read().await;
+        typemap.get::<UserData>().unwrap().search.clone()
+    };
+    let page = match provider.search(query, PAGE_SIZE, token).await {
+        Ok(page) if !page.results.is_empty() => page,
+        Ok(_) => return Err(format!("No results for `{query}`")),
+        Err(e) => {
+            error!("search for {query:?} failed: {e:?}");
+            return Err(format!("Search failed: {e}"));
+        }
+    };
+
+    let lines: Vec<String> = page
+        .results
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let details = x.details();
+            let details = if details.is_empty() {
+                String::new()
+            } else {
+                format!("\n-# {details}")
+            };
+            format!(
+                "{}. [{}](https://youtube.com/watch?v={}){details}",
+                i + 1,
+                x.title,
+                x.id
+            )
+        })
+        .collect();
+    let expires = started + SESSION_TIMEOUT.as_secs() as i64;
+
+    let menu_options = page
+        .results
+        .iter()
+        .map(|x| {
+            let mut option =
+                CreateSelectMenuOption::new(truncate(x.title.as_str(), 100), x.id.clone());
+            let details = x.details();
+            if !details.is_empty() {
+                option = option.description(truncate(&details, 100));
+            }
+            option
+        })
+        .collect();
+
+    Ok(CreateInteractionResponseMessage::new()
+        .embed(
+            CreateEmbed::new()
+                .color(Colour::new(COLOR_OK))
+                .title(format!("Results for {}", truncate(query, 200)))
+                .description(format!("{}\n\nExpires <t:{expires}:R>", lines.join("\n")))
+                .timestamp(Timestamp::now()),
+        )
+        .components(vec![
+            CreateActionRow::SelectMenu(
+                CreateSelectMenu::new(
+                    format!("{SELECT_PREFIX}:{started}"),
+                    CreateSelectMenuKind::String {
+                        options: menu_options,
+                    },
+                )
+                .placeholder("Select videos to queue")
+                .max_values(page.results.len() as u8),
+            ),
+            CreateActionRow::Buttons(vec![
+                page_button(started, "prev", page.prev.as_deref(), query).label("Previous"),
+                page_button(started, "next", page.next.as_deref(), query).label("Next"),
+            ]),
+        ]))
+}
+
+/// A button leading to the page `token` refers to, or a disabled one when
+/// there is no such page.
+fn page_button(started: i64, direction: &str, token: Option<&str>, query: &str) -> CreateButton {
+    let id = format!(
+        "{PAGE_PREFIX}{started}:{direction}:{}:",
+        token.unwrap_or("")
+    );
+    // Long queries are cut short to fit, later pages then search a little
+    // less precisely.
+    let room = MAX_CUSTOM_ID.saturating_sub(id.chars().count());
+    CreateButton::new(format!("{id}{}", truncate(query, room)))
+        .style(ButtonStyle::Secondary)
+        .disabled(token.is_none())
+}
+
+fn error_message(description: String) -> CreateInteractionResponseMessage {
+    CreateInteractionResponseMessage::new().embed(
+        CreateEmbed::new()
+            .color(Colour::new(COLOR_ERROR))
+            .description(description)
+            .title("Error")
+            .timestamp(Timestamp::now()),
+    )
+}
+
+fn truncate(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        None => s,
+        Some((idx, _)) => &s[..idx],
+    }
+}
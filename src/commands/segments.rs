@@ -0,0 +1,98 @@
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{COLOR_ERROR, COLOR_OK, UserData, segments::CATEGORIES};
+
+pub fn register() -> CreateCommand {
+    CATEGORIES.iter().fold(
+        CreateCommand::new("segments")
+            .description("Choose which parts of YouTube videos get skipped (Manage Server)"),
+        |command, (name, description)| {
+            command.add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                *name,
+                *description,
+            ))
+        },
+    )
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let guild_id = interaction.guild_id.unwrap();
+    let settings = {
+        let typemap = ctx.data.read().await;
+        typemap.get::<UserData>().unwrap().settings.clone()
+    };
+
+    let changes: Vec<(&str, bool)> = interaction
+        .data
+        .options()
+        .into_iter()
+        .filter_map(|o| match o.value {
+            ResolvedValue::Boolean(skip) => Some((o.name, skip)),
+            _ => None,
+        })
+        .collect();
+    let is_admin = interaction
+        .member
+        .as_ref()
+        .and_then(|m| m.permissions)
+        .is_some_and(|p| p.manage_guild());
+    if !changes.is_empty() && !is_admin {
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new().embed(
+                        CreateEmbed::new()
+                            .color(Colour::new(COLOR_ERROR))
+                            .title("Error")
+                            .description("You need Manage Server for that")
+                            .timestamp(Timestamp::now()),
+                    ),
+                ),
+            )
+            .await?;
+        return Ok(());
+    }
+    if !changes.is_empty() {
+        settings.update(guild_id, |s| {
+            for (name, skip) in changes {
+                s.skip_categories.retain(|c| c != name);
+                if skip {
+                    s.skip_categories.push(name.to_string());
+                }
+            }
+        });
+    }
+
+    let skipped = settings.get(guild_id).skip_categories;
+    let lines: Vec<String> = CATEGORIES
+        .iter()
+        .map(|(name, description)| {
+            let mark = if skipped.iter().any(|c| c == name) {
+                "✅"
+            } else {
+                "⬜"
+            };
+            format!("{mark} {description} (`{name}`)")
+        })
+        .collect();
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().embed(
+                    CreateEmbed::new()
+                        .color(Colour::new(COLOR_OK))
+                        .title("Skipped segments")
+                        .description(lines.join("\n"))
+                        .footer(CreateEmbedFooter::new("Applies from the next track"))
+                        .timestamp(Timestamp::now()),
+                ),
+            ),
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,299 @@
+use log::warn;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use songbird::tracks::Track;
+
+use crate::{COLOR_ERROR, COLOR_OK, UserData};
+
+/// Discord shows at most this many choices.
+const MAX_CHOICES: usize = 25;
+
+pub fn register() -> CreateCommand {
+    let name = |description: &str| {
+        CreateCommandOption::new(CommandOptionType::String, "name", description)
+            .required(true)
+            .set_autocomplete(true)
+    };
+    CreateCommand::new("sound")
+        .description("Play short clips over the music")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::SubCommand, "play", "Play a clip")
+                .add_sub_option(name("The clip to play")),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "list",
+            "List the clips",
+        ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "add",
+                "Upload a clip (Manage Server)",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::String, "name", "What to call it")
+                    .required(true),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Attachment,
+                    "file",
+                    "A short audio file",
+                )
+                .required(true),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "remove",
+                "Delete a clip (Manage Server)",
+            )
+            .add_sub_option(name("The clip to delete")),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "set",
+                "Change a clip's volume or cooldown (Manage Server)",
+            )
+            .add_sub_option(name("The clip to change"))
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "volume", "Percent")
+                    .min_int_value(0)
+                    .max_int_value(200),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "cooldown",
+                    "Seconds before it can play again",
+                )
+                .min_int_value(0)
+                .max_int_value(3600),
+            ),
+        )
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let Some(ResolvedOption {
+        name: subcommand,
+        value: ResolvedValue::SubCommand(options),
+        ..
+    }) = interaction.data.options().first().cloned()
+    else {
+        warn!("sound interaction option not subcommand");
+        return Ok(());
+    };
+    let mut name = "";
+    let mut file = None;
+    let mut volume = None;
+    let mut cooldown = None;
+    for option in &options {
+        match (option.name, &option.value) {
+            ("name", ResolvedValue::String(s)) => name = s,
+            ("file", ResolvedValue::Attachment(a)) => file = Some(*a),
+            ("volume", ResolvedValue::Integer(v)) => volume = Some(*v as u32),
+            ("cooldown", ResolvedValue::Integer(c)) => cooldown = Some(*c as u64),
+            _ => {}
+        }
+    }
+    let name = name.trim().to_lowercase();
+
+    let guild_id = interaction.guild_id.unwrap();
+    let is_admin = interaction
+        .member
+        .as_ref()
+        .and_then(|m| m.permissions)
+        .is_some_and(|p| p.manage_guild());
+    if matches!(subcommand, "add" | "remove" | "set") && !is_admin {
+        return respond(
+            ctx,
+            interaction,
+            Err("You need Manage Server for that".into()),
+        )
+        .await;
+    }
+    let (soundboard, http) = {
+        let typemap = ctx.data.read().await;
+        let data = typemap.get::<UserData>().unwrap();
+        (data.soundboard.clone(), data.http.clone())
+    };
+
+    let result = match subcommand {
+        "list" => {
+            let names = soundboard.names(guild_id);
+            if names.is_empty() {
+                Err("No clips yet, add one with `/sound add`".to_string())
+            } else {
+                Ok(CreateEmbed::new().title("Sounds").description(
+                    names
+                        .iter()
+                        .map(|n| format!("`{n}`"))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ))
+            }
+        }
+        "add" => {
+            let Some(file) = file else {
+                return Ok(());
+            };
+            // Downloading and checking the file can take a moment.
+            interaction.defer(ctx).await?;
+            let added = soundboard
+                .add(
+                    &http,
+                    guild_id,
+                    &name,
+                    &file.url,
+                    &file.filename,
+                    file.size.into(),
+                )
+                .await;
+            let result = match added {
+                Ok(()) => Ok(CreateEmbed::new()
+                    .title("Sound added")
+                    .description(format!("Play it with `/sound play {name}`"))),
+                Err(e) => Err(format!("{e:#}")),
+            };
+            return followup(ctx, interaction, result).await;
+        }
+        "remove" => {
+            if soundboard.remove(guild_id, &name).await {
+                Ok(CreateEmbed::new().title(format!("Removed {name}")))
+            } else {
+                Err(format!("There's no sound called {name}"))
+            }
+        }
+        "set" => match soundboard.configure(guild_id, &name, volume, cooldown) {
+            Some(clip) => Ok(CreateEmbed::new()
+                .title(format!("Updated {name}"))
+                .description(format!(
+                    "Volume {}%, cooldown {}s",
+                    clip.volume, clip.cooldown
+                ))),
+            None => Err(format!("There's no sound called {name}")),
+        },
+        "play" => {
+            // The first play decodes the clip.
+            interaction.defer(ctx).await?;
+            let result = play(ctx, interaction, guild_id, &name).await;
+            return followup(ctx, interaction, result).await;
+        }
+        _ => return Ok(()),
+    };
+    respond(ctx, interaction, result).await
+}
+
+/// Mixes the clip over whatever is playing, joining the caller's channel if
+/// the bot isn't in one.
+async fn play(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    guild_id: GuildId,
+    name: &str,
+) -> Result<CreateEmbed, String> {
+    let channel_id = ctx
+        .cache
+        .guild(guild_id)
+        .and_then(|g| g.voice_states.get(&interaction.user.id)?.channel_id);
+
+    let (call, soundboard) = {
+        let typemap = ctx.data.read().await;
+        let data = typemap.get::<UserData>().unwrap();
+        (data.songbird.get(guild_id), data.soundboard.clone())
+    };
+    let call = match (call, channel_id) {
+        (Some(call), _) => call,
+        (None, Some(channel_id)) => super::play::join(ctx, guild_id, channel_id)
+            .await
+            .ok_or("Could not join your voice channel")?,
+        (None, None) => return Err("Join a voice channel first".into()),
+    };
+    // The first play of a clip decodes all of it.
+    let (input, clip) = soundboard
+        .input(guild_id, name)
+        .await
+        .map_err(|e| format!("{e:#}"))?;
+    // A separate track, so the music keeps going underneath.
+    call.lock()
+        .await
+        .play(Track::from(input).volume(clip.volume as f32 / 100.0));
+    Ok(CreateEmbed::new().title(format!("Playing {name}")))
+}
+
+/// Suggests the guild's clips for `/sound ... name`.
+pub async fn autocomplete(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+) -> Result<(), serenity::Error> {
+    let Some(focused) = interaction.data.autocomplete() else {
+        return Ok(());
+    };
+    let typed = focused.value.trim().to_lowercase();
+    let names = match interaction.guild_id {
+        Some(guild_id) => {
+            let typemap = ctx.data.read().await;
+            typemap
+                .get::<UserData>()
+                .unwrap()
+                .soundboard
+                .names(guild_id)
+        }
+        None => vec![],
+    };
+
+    let response = names
+        .into_iter()
+        .filter(|n| n.contains(&typed))
+        .take(MAX_CHOICES)
+        .fold(CreateAutocompleteResponse::new(), |response, name| {
+            response.add_string_choice(name.clone(), name)
+        });
+    interaction
+        .create_response(ctx, CreateInteractionResponse::Autocomplete(response))
+        .await
+}
+
+fn embed(result: Result<CreateEmbed, String>) -> CreateEmbed {
+    match result {
+        Ok(embed) => embed.color(Colour::new(COLOR_OK)),
+        Err(e) => CreateEmbed::new()
+            .color(Colour::new(COLOR_ERROR))
+            .description(e)
+            .title("Error"),
+    }
+    .timestamp(Timestamp::now())
+}
+
+async fn respond(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    result: Result<CreateEmbed, String>,
+) -> Result<(), serenity::Error> {
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().embed(embed(result)),
+            ),
+        )
+        .await
+}
+
+async fn followup(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    result: Result<CreateEmbed, String>,
+) -> Result<(), serenity::Error> {
+    interaction
+        .create_followup(
+            ctx,
+            CreateInteractionResponseFollowup::new().embed(embed(result)),
+        )
+        .await?;
+    Ok(())
+}
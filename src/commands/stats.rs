@@ -0,0 +1,122 @@
+use std::{collections::HashMap, time::Duration};
+
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use url::Url;
+
+use crate::{COLOR_OK, UserData, history::by_track, search::clock};
+
+const TOP: usize = 5;
+/// Choices for `period`, with their length in seconds.
+const PERIODS: &[(&str, &str, Option<i64>)] = &[
+    ("day", "Last 24 hours", Some(24 * 60 * 60)),
+    ("week", "Last 7 days", Some(7 * 24 * 60 * 60)),
+    ("month", "Last 30 days", Some(30 * 24 * 60 * 60)),
+    ("year", "Last 365 days", Some(365 * 24 * 60 * 60)),
+    ("all", "All time", None),
+];
+
+pub fn register() -> CreateCommand {
+    let period = PERIODS.iter().fold(
+        CreateCommandOption::new(
+            CommandOptionType::String,
+            "period",
+            "How far back to look, the last 7 days by default",
+        ),
+        |option, (value, name, _)| option.add_string_choice(*name, *value),
+    );
+    CreateCommand::new("stats")
+        .description("Show what gets played here the most")
+        .add_option(period)
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let guild_id = interaction.guild_id.unwrap();
+    let chosen = match interaction.data.options().first().map(|o| &o.value) {
+        Some(ResolvedValue::String(value)) => *value,
+        _ => "week",
+    };
+    let (_, name, length) = PERIODS
+        .iter()
+        .find(|(value, _, _)| *value == chosen)
+        .unwrap_or(&PERIODS[1]);
+    let since = length.map(|l| Timestamp::now().unix_timestamp() - l);
+
+    let history = {
+        let typemap = ctx.data.read().await;
+        typemap.get::<UserData>().unwrap().history.clone()
+    };
+    let plays = tokio::task::spawn_blocking(move || history.plays(guild_id, since))
+        .await
+        .unwrap_or_default();
+
+    let mut embed = CreateEmbed::new()
+        .color(Colour::new(COLOR_OK))
+        .title(format!("Stats · {name}"))
+        .timestamp(Timestamp::now());
+    if plays.is_empty() {
+        embed = embed.description("Nothing was played in that time");
+    } else {
+        let tracks: Vec<String> = by_track(&plays)
+            .into_iter()
+            .take(TOP)
+            .enumerate()
+            .map(|(i, (play, count))| {
+                let title = match Url::parse(&play.url) {
+                    Ok(url) if url.scheme() != "file" => format!("[{}]({url})", play.title),
+                    _ => play.title.clone(),
+                };
+                format!("{}. {title} · {count} {}", i + 1, plural(count, "play"))
+            })
+            .collect();
+
+        let mut requesters: HashMap<UserId, usize> = HashMap::new();
+        for user in plays.iter().filter_map(|p| p.requested_by) {
+            *requesters.entry(user).or_default() += 1;
+        }
+        let mut requesters: Vec<(UserId, usize)> = requesters.into_iter().collect();
+        requesters.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        let requesters: Vec<String> = requesters
+            .into_iter()
+            .take(TOP)
+            .enumerate()
+            .map(|(i, (user, count))| {
+                format!("{}. <@{user}> · {count} {}", i + 1, plural(count, "track"))
+            })
+            .collect();
+
+        let listened: Duration = plays.iter().map(|p| p.listened).sum();
+        embed = embed
+            .field("Top tracks", tracks.join("\n"), false)
+            .field(
+                "Top requesters",
+                if requesters.is_empty() {
+                    "Only the radio so far".to_string()
+                } else {
+                    requesters.join("\n")
+                },
+                false,
+            )
+            .field("Listening time", clock(listened), true)
+            .field("Tracks played", plays.len().to_string(), true);
+    }
+
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().embed(embed),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        word.to_string()
+    } else {
+        format!("{word}s")
+    }
+}
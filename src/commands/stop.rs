@@ -12,6 +12,7 @@ pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(),
     let guild_id = interaction.guild_id.unwrap();
     let mut typemap = ctx.data.write().await;
     let data = typemap.get_mut::<UserData>().unwrap();
+    crate::queue::clear(data, guild_id);
     if let Some(track) = data.track_handles.get_mut(&guild_id) {
         let _ = track.stop();
     }
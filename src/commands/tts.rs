@@ -0,0 +1,103 @@
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{COLOR_ERROR, COLOR_OK, UserData, tts::is_valid_voice};
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("tts")
+        .description("Set up the bot's voice (Manage Server)")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "voice",
+            "Voice or language, e.g. en-us or de; \"default\" to reset",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "announce",
+            "Say \"Now playing\" before each track",
+        ))
+}
+
+pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
+    let guild_id = interaction.guild_id.unwrap();
+    let settings = {
+        let typemap = ctx.data.read().await;
+        typemap.get::<UserData>().unwrap().settings.clone()
+    };
+
+    let options = interaction.data.options();
+    let is_admin = interaction
+        .member
+        .as_ref()
+        .and_then(|m| m.permissions)
+        .is_some_and(|p| p.manage_guild());
+    let invalid = options.iter().any(|o| match o.value {
+        ResolvedValue::String(voice) => voice.trim() != "default" && !is_valid_voice(voice.trim()),
+        _ => false,
+    });
+    let error = if !options.is_empty() && !is_admin {
+        Some("You need Manage Server for that")
+    } else if invalid {
+        Some("Voices are names like `en-us` or `de`")
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new().embed(
+                        CreateEmbed::new()
+                            .color(Colour::new(COLOR_ERROR))
+                            .description(error)
+                            .title("Error")
+                            .timestamp(Timestamp::now()),
+                    ),
+                ),
+            )
+            .await?;
+        return Ok(());
+    }
+    if !options.is_empty() {
+        settings.update(guild_id, |s| {
+            for option in options {
+                match (option.name, option.value) {
+                    ("voice", ResolvedValue::String(voice)) => {
+                        let voice = voice.trim();
+                        s.tts_voice = (voice != "default").then(|| voice.to_string());
+                    }
+                    ("announce", ResolvedValue::Boolean(announce)) => s.announce = announce,
+                    _ => {}
+                }
+            }
+        });
+    }
+
+    let current = settings.get(guild_id);
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().embed(
+                    CreateEmbed::new()
+                        .color(Colour::new(COLOR_OK))
+                        .title("Voice")
+                        .field(
+                            "Voice",
+                            current.tts_voice.as_deref().unwrap_or("default"),
+                            true,
+                        )
+                        .field(
+                            "Announcements",
+                            if current.announce { "on" } else { "off" },
+                            true,
+                        )
+                        .timestamp(Timestamp::now()),
+                ),
+            ),
+        )
+        .await?;
+    Ok(())
+}
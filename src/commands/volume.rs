@@ -25,13 +25,13 @@ pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(),
     let d = ctx.data.clone();
     let mut typemap = d.write().await;
     let data = typemap.get_mut::<UserData>().unwrap();
-    if let Some(track) = data.track_handles.get_mut(&guild_id) {
+    if data.track_handles.contains_key(&guild_id) {
         if let Some(ResolvedOption {
             value: ResolvedValue::Number(num),
             ..
         }) = interaction.data.options().first().cloned()
         {
-            let _ = track.set_volume(num as f32 / 100.0);
+            data.volume(guild_id).set(num as f32 / 100.0);
             interaction
                 .create_response(
                     ctx,
@@ -0,0 +1,230 @@
+use std::{
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use serenity::{all::GuildId, async_trait, cache::Cache};
+use songbird::{
+    Call, CoreEvent, Event, EventContext, EventHandler, model::payload::Speaking,
+    tracks::TrackHandle,
+};
+
+use crate::settings::Settings;
+
+/// What every track starts at, `/volume` changes it for the current one.
+pub const DEFAULT_VOLUME: f32 = 0.5;
+/// How loud the music stays while the bot itself is speaking.
+const SPEECH_GAIN: f32 = 0.3;
+/// Voice ticks come every 20ms.
+const TICK: Duration = Duration::from_millis(20);
+/// Quiet this long between words doesn't count as having stopped talking.
+const HOLD: Duration = Duration::from_millis(300);
+
+/// The music's volume in a guild, and whatever is turning it down for now:
+/// the bot speaking, or people talking in the channel.
+pub struct Volume {
+    state: Mutex<VolumeState>,
+    /// Set while a [`Listener`] is attached to the guild's call.
+    listener: Mutex<Weak<AtomicBool>>,
+}
+
+struct VolumeState {
+    track: Option<TrackHandle>,
+    volume: f32,
+    /// Speech tracks playing over the music.
+    speaking: usize,
+    /// From auto-ducking, 1.0 when nobody talks.
+    talking: f32,
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(VolumeState {
+                track: None,
+                volume: DEFAULT_VOLUME,
+                speaking: 0,
+                talking: 1.0,
+            }),
+            listener: Mutex::default(),
+        }
+    }
+}
+
+impl Volume {
+    /// Makes `track` the music, at the default volume.
+    pub fn set_track(&self, track: TrackHandle) {
+        let mut state = self.state.lock().unwrap();
+        state.track = Some(track);
+        state.volume = DEFAULT_VOLUME;
+        state.apply();
+    }
+
+    pub fn set(&self, volume: f32) {
+        let mut state = self.state.lock().unwrap();
+        state.volume = volume;
+        state.apply();
+    }
+
+    /// Turns the music down until as many [`Volume::speech_ended`] calls.
+    pub fn speech_started(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.speaking += 1;
+        state.apply();
+    }
+
+    pub fn speech_ended(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.speaking = state.speaking.saturating_sub(1);
+        state.apply();
+    }
+
+    fn set_talking(&self, gain: f32) {
+        let mut state = self.state.lock().unwrap();
+        if state.talking != gain {
+            state.talking = gain;
+            state.apply();
+        }
+    }
+
+    fn talking(&self) -> f32 {
+        self.state.lock().unwrap().talking
+    }
+}
+
+impl VolumeState {
+    fn apply(&self) {
+        let speech = if self.speaking > 0 { SPEECH_GAIN } else { 1.0 };
+        if let Some(track) = &self.track {
+            let _ = track.set_volume(self.volume * speech.min(self.talking));
+        }
+    }
+}
+
+/// Starts auto-ducking in `call` unless it already is. Stops by itself once
+/// the guild turns it off.
+pub fn listen(
+    call: &mut Call,
+    cache: Arc<Cache>,
+    settings: Arc<Settings>,
+    volume: Arc<Volume>,
+    guild_id: GuildId,
+) {
+    let mut listener = volume.listener.lock().unwrap();
+    if listener
+        .upgrade()
+        .is_some_and(|active| active.load(Ordering::Relaxed))
+    {
+        return;
+    }
+    let active = Arc::new(AtomicBool::new(true));
+    *listener = Arc::downgrade(&active);
+    drop(listener);
+
+    let handler = Listener {
+        cache,
+        settings,
+        volume,
+        guild_id,
+        bots: Arc::default(),
+        last_voice: Arc::new(Mutex::new(None)),
+        active,
+    };
+    call.add_global_event(CoreEvent::SpeakingStateUpdate.into(), handler.clone());
+    call.add_global_event(CoreEvent::VoiceTick.into(), handler);
+}
+
+/// Ducks the music while anyone who isn't a bot is talking.
+#[derive(Clone)]
+struct Listener {
+    cache: Arc<Cache>,
+    settings: Arc<Settings>,
+    volume: Arc<Volume>,
+    guild_id: GuildId,
+    /// Whether each SSRC belongs to a bot.
+    bots: Arc<DashMap<u32, bool>>,
+    last_voice: Arc<Mutex<Option<Instant>>>,
+    /// Shared by both registrations, so they stop together.
+    active: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl EventHandler for Listener {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if !self.active.load(Ordering::Relaxed) {
+            return Some(Event::Cancel);
+        }
+        let settings = self.settings.get(self.guild_id);
+        if !settings.auto_duck {
+            self.active.store(false, Ordering::Relaxed);
+            self.volume.set_talking(1.0);
+            return Some(Event::Cancel);
+        }
+
+        match ctx {
+            EventContext::SpeakingStateUpdate(Speaking {
+                ssrc,
+                user_id: Some(user_id),
+                ..
+            }) => {
+                let is_bot = self.cache.user(user_id.0).is_some_and(|u| u.bot);
+                self.bots.insert(*ssrc, is_bot);
+            }
+            EventContext::VoiceTick(tick) => {
+                let threshold = settings.duck_threshold as f32;
+                let talking = tick.speaking.iter().any(|(ssrc, data)| {
+                    !self.bots.get(ssrc).is_some_and(|b| *b)
+                        && data
+                            .decoded_voice
+                            .as_deref()
+                            .is_some_and(|voice| level(voice) > threshold)
+                });
+
+                let mut last_voice = self.last_voice.lock().unwrap();
+                if talking {
+                    *last_voice = Some(Instant::now());
+                }
+                let held = last_voice.is_some_and(|at| at.elapsed() < HOLD);
+                drop(last_voice);
+
+                let ducked = 1.0 - settings.duck_amount.min(100) as f32 / 100.0;
+                let gain = self.volume.talking();
+                // Move a tick's worth towards where it should be, so a full
+                // swing takes the attack or release time.
+                let swing = 1.0 - ducked;
+                let next = if held {
+                    let step = swing * ratio(TICK, settings.duck_attack_ms);
+                    (gain - step).max(ducked)
+                } else {
+                    let step = swing * ratio(TICK, settings.duck_release_ms);
+                    (gain + step).min(1.0)
+                };
+                self.volume.set_talking(next);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+fn ratio(tick: Duration, ms: u64) -> f32 {
+    if ms == 0 {
+        1.0
+    } else {
+        (tick.as_secs_f32() * 1000.0 / ms as f32).min(1.0)
+    }
+}
+
+/// RMS level of 16-bit samples in dBFS.
+fn level(samples: &[i16]) -> f32 {
+    if samples.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+    let sum: f64 = samples.iter().map(|&s| (s as f64).powi(2)).sum();
+    let rms = (sum / samples.len() as f64).sqrt() / i16::MAX as f64;
+    20.0 * rms.max(1e-9).log10() as f32
+}
@@ -0,0 +1,142 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::Mutex,
+    time::Duration,
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serenity::all::{GuildId, UserId};
+
+/// Past this size a guild's history is trimmed down to its newest plays.
+const MAX_BYTES: u64 = 8 * 1024 * 1024;
+/// Trimming keeps this share of `MAX_BYTES`, so it doesn't happen on every
+/// play once the limit is reached.
+const KEEP_BYTES: u64 = MAX_BYTES / 4 * 3;
+
+/// What each guild played, a JSON line per track in `history/<guild>.jsonl`
+/// under the data directory. Appended to, and trimmed from the front once
+/// it gets too big.
+pub struct History {
+    dir: PathBuf,
+    /// Keeps appends and trims whole.
+    writing: Mutex<()>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Play {
+    pub url: String,
+    pub title: String,
+    /// `None` for radio picks.
+    pub requested_by: Option<UserId>,
+    /// Unix seconds.
+    pub started_at: i64,
+    /// How long it actually played, loops included.
+    pub listened: Duration,
+}
+
+impl History {
+    pub fn load() -> Self {
+        Self {
+            dir: crate::settings::dir().join("history"),
+            writing: Mutex::default(),
+        }
+    }
+
+    fn path(&self, guild_id: GuildId) -> PathBuf {
+        self.dir.join(format!("{guild_id}.jsonl"))
+    }
+
+    pub fn record(&self, guild_id: GuildId, play: &Play) {
+        let _writing = self.writing.lock().unwrap();
+        let write = || -> std::io::Result<()> {
+            std::fs::create_dir_all(&self.dir)?;
+            let mut line = serde_json::to_vec(play)?;
+            line.push(b'\n');
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path(guild_id))?;
+            file.write_all(&line)?;
+            if file.metadata()?.len() > MAX_BYTES {
+                self.trim(guild_id)?;
+            }
+            Ok(())
+        };
+        if let Err(e) = write() {
+            warn!("could not save history: {e}");
+        }
+    }
+
+    /// Drops the oldest plays, keeping the newest `KEEP_BYTES` worth.
+    fn trim(&self, guild_id: GuildId) -> std::io::Result<()> {
+        let path = self.path(guild_id);
+        let lines: Vec<String> = BufReader::new(std::fs::File::open(&path)?)
+            .lines()
+            .collect::<std::io::Result<_>>()?;
+        let mut kept = 0;
+        let start = lines
+            .iter()
+            .rposition(|line| {
+                kept += line.len() as u64 + 1;
+                kept > KEEP_BYTES
+            })
+            .map_or(0, |i| i + 1);
+
+        let partial = path.with_extension("jsonl.part");
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&partial)?);
+        for line in &lines[start..] {
+            file.write_all(line.as_bytes())?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+        std::fs::rename(partial, path)
+    }
+
+    /// Plays started at or after `since`, newest first. Lines that don't
+    /// parse are skipped.
+    pub fn plays(&self, guild_id: GuildId, since: Option<i64>) -> Vec<Play> {
+        let Ok(file) = std::fs::File::open(self.path(guild_id)) else {
+            return vec![];
+        };
+        let mut plays: Vec<Play> = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<Play>(&line).ok())
+            .filter(|p| since.is_none_or(|since| p.started_at >= since))
+            .collect();
+        plays.reverse();
+        plays
+    }
+
+    /// Up to `limit` different tracks whose title contains `needle`
+    /// (lowercase), most recently played first.
+    pub fn matching(&self, guild_id: GuildId, needle: &str, limit: usize) -> Vec<Play> {
+        let mut found: Vec<Play> = vec![];
+        for play in self.plays(guild_id, None) {
+            if found.len() == limit {
+                break;
+            }
+            if play.title.to_lowercase().contains(needle)
+                && !found.iter().any(|p| p.url == play.url)
+            {
+                found.push(play);
+            }
+        }
+        found
+    }
+}
+
+/// Each track in `plays` with how often it was played, most played first.
+/// The title is from the latest play.
+pub fn by_track(plays: &[Play]) -> Vec<(&Play, usize)> {
+    let mut counts: HashMap<&str, (&Play, usize)> = HashMap::new();
+    for play in plays {
+        counts.entry(&play.url).or_insert((play, 0)).1 += 1;
+    }
+    let mut counts: Vec<(&Play, usize)> = counts.into_values().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.started_at.cmp(&a.0.started_at)));
+    counts
+}
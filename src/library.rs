@@ -0,0 +1,378 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use log::{info, warn};
+use notify::{RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey},
+    probe::Hint,
+};
+use tokio::sync::mpsc;
+use url::Url;
+
+/// Changes come in bursts while files are copied, so scanning waits until
+/// there haven't been any for this long.
+const DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// Extensions worth probing, everything else in the directory is skipped.
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "ogg", "oga", "opus", "wav", "m4a", "aac", "mp4", "mka", "webm",
+];
+
+/// A directory of music, indexed by tags into `library.json` so lookups
+/// don't touch the files.
+pub struct Library {
+    dir: PathBuf,
+    index_dir: PathBuf,
+    tracks: RwLock<Vec<LibraryTrack>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LibraryTrack {
+    pub path: PathBuf,
+    /// Modification time in unix seconds, to tell when to index again.
+    modified: u64,
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub duration: Option<Duration>,
+    /// Extracted cover art, stored next to the index.
+    pub cover: Option<PathBuf>,
+}
+
+impl LibraryTrack {
+    /// `Artist - Title`, or just the title.
+    pub fn display(&self) -> String {
+        match &self.artist {
+            Some(artist) => format!("{artist} - {}", self.title),
+            None => self.title.clone(),
+        }
+    }
+
+    pub fn url(&self) -> Url {
+        Url::from_file_path(&self.path).expect("library paths are absolute")
+    }
+
+    fn haystack(&self) -> String {
+        [
+            Some(self.title.as_str()),
+            self.artist.as_deref(),
+            self.album.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+    }
+}
+
+impl Library {
+    /// `LIBRARY_DIR` is the music, rescanned whenever it changes. Where it
+    /// can't be watched, it's rescanned every `LIBRARY_SCAN_SECS` (default
+    /// 300) instead. `None` when unset.
+    pub fn from_env() -> Option<Arc<Self>> {
+        let dir = std::env::var("LIBRARY_DIR").ok()?;
+        let dir = match std::fs::canonicalize(&dir) {
+            Ok(dir) => dir,
+            Err(e) => {
+                warn!("LIBRARY_DIR {dir}: {e}");
+                return None;
+            }
+        };
+        let interval = std::env::var("LIBRARY_SCAN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300);
+
+        let index_dir = crate::cache::dir().join("library");
+        let tracks = std::fs::read(index_dir.join("library.json"))
+            .ok()
+            .and_then(|b| serde_json::from_slice(&b).ok())
+            .unwrap_or_default();
+        let library = Arc::new(Self {
+            dir,
+            index_dir,
+            tracks: RwLock::new(tracks),
+        });
+
+        tokio::spawn(watch(library.clone(), Duration::from_secs(interval)));
+        Some(library)
+    }
+
+    /// Tracks matching every word of `query` in their title, artist or album.
+    pub fn search(&self, query: &str) -> Vec<LibraryTrack> {
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        self.filter(|t| {
+            let haystack = t.haystack();
+            words.iter().all(|w| haystack.contains(w))
+        })
+    }
+
+    /// The tracks of the first album matching `name`, in order.
+    pub fn album(&self, name: &str) -> Vec<LibraryTrack> {
+        let name = name.to_lowercase();
+        let Some(album) = self
+            .filter(|t| {
+                t.album
+                    .as_ref()
+                    .is_some_and(|a| a.to_lowercase().contains(&name))
+            })
+            .into_iter()
+            .find_map(|t| t.album)
+        else {
+            return vec![];
+        };
+        self.filter(|t| t.album.as_ref() == Some(&album))
+    }
+
+    pub fn artist(&self, name: &str) -> Vec<LibraryTrack> {
+        let name = name.to_lowercase();
+        self.filter(|t| {
+            t.artist
+                .as_ref()
+                .is_some_and(|a| a.to_lowercase().contains(&name))
+        })
+    }
+
+    /// The indexed track at `path`.
+    pub fn track(&self, path: &Path) -> Option<LibraryTrack> {
+        self.tracks
+            .read()
+            .unwrap()
+            .iter()
+            .find(|t| t.path == path)
+            .cloned()
+    }
+
+    /// Whether `path` is an indexed track in the library directory, symlinks
+    /// and `..` resolved.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.canonicalize()
+            .is_ok_and(|p| p.starts_with(&self.dir) && self.track(path).is_some())
+    }
+
+    /// Sorted by artist, album, then track number.
+    fn filter(&self, f: impl Fn(&LibraryTrack) -> bool) -> Vec<LibraryTrack> {
+        let mut found: Vec<LibraryTrack> = self
+            .tracks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|t| f(t))
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| {
+            (&a.artist, &a.album, a.track_number, &a.path).cmp(&(
+                &b.artist,
+                &b.album,
+                b.track_number,
+                &b.path,
+            ))
+        });
+        found
+    }
+
+    /// Indexes new and changed files and forgets removed ones.
+    fn scan(&self) {
+        let known: HashMap<PathBuf, LibraryTrack> = self
+            .tracks
+            .read()
+            .unwrap()
+            .iter()
+            .map(|t| (t.path.clone(), t.clone()))
+            .collect();
+
+        let mut tracks = vec![];
+        let mut changed = false;
+        for entry in walkdir::WalkDir::new(&self.dir)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(Result::ok)
+        {
+            let path = entry.path();
+            let is_audio = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()));
+            if !entry.file_type().is_file() || !is_audio {
+                continue;
+            }
+            // Links are followed, but only to files inside the library.
+            if !path.canonicalize().is_ok_and(|p| p.starts_with(&self.dir)) {
+                continue;
+            }
+            let modified = entry
+                .metadata()
+                .ok()
+                .and_then(|m| m.modified().ok())
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+
+            match known.get(path) {
+                Some(track) if track.modified == modified => tracks.push(track.clone()),
+                _ => {
+                    changed = true;
+                    match self.index(path, modified) {
+                        Ok(track) => tracks.push(track),
+                        Err(e) => warn!("could not index {}: {e}", path.display()),
+                    }
+                }
+            }
+        }
+        changed |= tracks.len() != known.len();
+        if !changed {
+            return;
+        }
+
+        info!("Library has {} tracks", tracks.len());
+        let write = || -> anyhow::Result<()> {
+            std::fs::create_dir_all(&self.index_dir)?;
+            std::fs::write(
+                self.index_dir.join("library.json"),
+                serde_json::to_vec(&tracks)?,
+            )?;
+            Ok(())
+        };
+        if let Err(e) = write() {
+            warn!("could not save library index: {e}");
+        }
+        *self.tracks.write().unwrap() = tracks;
+    }
+
+    fn index(&self, path: &Path, modified: u64) -> anyhow::Result<LibraryTrack> {
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+        let mss = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+        let mut probed = symphonia::default::get_probe().format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+
+        let duration = probed.format.default_track().and_then(|t| {
+            let params = &t.codec_params;
+            let time = params.time_base?.calc_time(params.n_frames?);
+            Some(Duration::from_secs_f64(time.seconds as f64 + time.frac))
+        });
+
+        let mut track = LibraryTrack {
+            path: path.to_path_buf(),
+            modified,
+            title: path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            artist: None,
+            album: None,
+            track_number: None,
+            duration,
+            cover: None,
+        };
+        // Tags can be ahead of the container (ID3v2) or inside it.
+        if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+            self.apply(&mut track, revision);
+        }
+        if let Some(revision) = probed.format.metadata().current() {
+            self.apply(&mut track, revision);
+        }
+        Ok(track)
+    }
+
+    fn apply(&self, track: &mut LibraryTrack, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let value = tag.value.to_string();
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => track.title = value,
+                Some(StandardTagKey::Artist) => track.artist = Some(value),
+                Some(StandardTagKey::AlbumArtist) if track.artist.is_none() => {
+                    track.artist = Some(value)
+                }
+                Some(StandardTagKey::Album) => track.album = Some(value),
+                Some(StandardTagKey::TrackNumber) => {
+                    // Sometimes `3/12`.
+                    track.track_number = value.split('/').next().and_then(|n| n.parse().ok());
+                }
+                _ => {}
+            }
+        }
+
+        if track.cover.is_none()
+            && let Some(visual) = revision.visuals().first()
+        {
+            let extension = match visual.media_type.as_str() {
+                "image/png" => "png",
+                _ => "jpg",
+            };
+            // Albums share their art, so store it by content.
+            let path = self.index_dir.join("covers").join(format!(
+                "{}.{extension}",
+                hex::encode(Sha256::digest(&visual.data))
+            ));
+            let write = || -> std::io::Result<()> {
+                if !path.exists() {
+                    std::fs::create_dir_all(path.parent().unwrap())?;
+                    std::fs::write(&path, &visual.data)?;
+                }
+                Ok(())
+            };
+            match write() {
+                Ok(()) => track.cover = Some(path),
+                Err(e) => warn!("could not save cover art: {e}"),
+            }
+        }
+    }
+}
+
+/// Scans once, then again after every burst of changes to the directory.
+/// Falls back to scanning every `interval` when it can't be watched.
+async fn watch(library: Arc<Library>, interval: Duration) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok_and(|e| !e.kind.is_access()) {
+            let _ = tx.send(());
+        }
+    })
+    .and_then(|mut watcher| {
+        watcher.watch(&library.dir, RecursiveMode::Recursive)?;
+        Ok(watcher)
+    });
+    let watcher = match watcher {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            warn!(
+                "could not watch {}, rescanning every {interval:?}: {e}",
+                library.dir.display()
+            );
+            None
+        }
+    };
+
+    loop {
+        let scanning = library.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || scanning.scan()).await {
+            warn!("library scan panicked: {e}");
+        }
+        if watcher.is_none() {
+            tokio::time::sleep(interval).await;
+            continue;
+        }
+        if rx.recv().await.is_none() {
+            return;
+        }
+        while let Ok(Some(())) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {}
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    io::{ErrorKind, SeekFrom},
+    pin::Pin,
+    process::{Command, Stdio},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::StreamExt;
+use log::{info, warn};
+use reqwest::{Client as HttpClient, Response};
+use serenity::async_trait;
+use songbird::input::{
+    AsyncAdapterStream, AsyncMediaSource, AudioStream, ChildContainer, Input, LiveInput,
+};
+use symphonia::core::{io::MediaSource, probe::Hint};
+use tokio::{
+    io::{AsyncRead, AsyncSeek, ReadBuf},
+    sync::{mpsc, watch},
+};
+use url::Url;
+
+/// Chunks buffered between the network and the decoder.
+const BUFFERED_CHUNKS: usize = 64;
+/// Bytes songbird's adapter keeps ready for the decoder.
+const ADAPTER_BYTES: usize = 64 * 1024;
+/// Reconnect attempts in a row before giving up on a stream.
+const MAX_RETRIES: u32 = 10;
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Only for the response headers, the body is endless.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An endless stream, e.g. internet radio. It can't be seeked or looped.
+pub struct Live {
+    /// The station name, from `icy-name`.
+    pub name: Option<String>,
+    /// The song currently on air, from ICY metadata. Closed once the stream
+    /// has been given up on or the track is gone.
+    pub title: watch::Receiver<Option<String>>,
+}
+
+/// Streams `url` with ICY metadata, reconnecting whenever it drops. Carries
+/// on with `first` if it was already connected to.
+pub async fn open(http: HttpClient, url: Url, first: Option<Response>) -> (Input, Live) {
+    let (tx, rx) = mpsc::channel(BUFFERED_CHUNKS);
+    let (title_tx, title_rx) = watch::channel(None);
+
+    // Connect once up front for the headers, the rest happens in the pump.
+    let first = match first {
+        Some(response) => Ok(response),
+        None => connect(&http, &url).await,
+    };
+    let (name, hint) = match &first {
+        Ok(response) => {
+            let header = |name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_owned)
+            };
+            let mut hint = Hint::new();
+            if let Some(content_type) = header("content-type") {
+                hint.mime_type(&content_type);
+            }
+            (header("icy-name"), Some(hint))
+        }
+        Err(_) => (None, None),
+    };
+    tokio::spawn(pump(http, url, first.ok(), tx, title_tx));
+
+    let input = Input::Live(
+        LiveInput::Raw(AudioStream {
+            input: Box::new(AsyncAdapterStream::new(
+                Box::new(StreamReader {
+                    rx,
+                    pending: vec![],
+                    offset: 0,
+                }),
+                ADAPTER_BYTES,
+            )) as Box<dyn MediaSource>,
+            hint,
+        }),
+        None,
+    );
+    (
+        input,
+        Live {
+            name,
+            title: title_rx,
+        },
+    )
+}
+
+/// HLS needs segments fetched and demuxed, which symphonia can't do, so
+/// `ffmpeg` does it and hands over FLAC.
+pub fn open_hls(url: &Url) -> std::io::Result<(Input, Live)> {
+    let child = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-i", url.as_str()])
+        .args(["-vn", "-f", "flac", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // No metadata to follow, the sender is dropped straight away.
+    let (_, title) = watch::channel(None);
+    Ok((
+        ChildContainer::from(child).into(),
+        Live { name: None, title },
+    ))
+}
+
+async fn connect(http: &HttpClient, url: &Url) -> anyhow::Result<reqwest::Response> {
+    let request = http.get(url.clone()).header("Icy-MetaData", "1").send();
+    Ok(tokio::time::timeout(CONNECT_TIMEOUT, request)
+        .await??
+        .error_for_status()?)
+}
+
+/// Moves audio from the network to the reader until the reader is dropped,
+/// reconnecting with backoff. Consecutive failures end the stream.
+async fn pump(
+    http: HttpClient,
+    url: Url,
+    mut response: Option<reqwest::Response>,
+    tx: mpsc::Sender<Vec<u8>>,
+    title_tx: watch::Sender<Option<String>>,
+) {
+    let mut failures = 0;
+    loop {
+        if let Some(r) = response.take() {
+            match stream(r, &tx, &title_tx).await {
+                Ok(0) => {}
+                Ok(_) => failures = 0,
+                Err(e) => warn!("live stream {url} failed: {e}"),
+            }
+        }
+        if tx.is_closed() {
+            return;
+        }
+
+        failures += 1;
+        if failures > MAX_RETRIES {
+            warn!("giving up on live stream {url}");
+            return;
+        }
+        let backoff = Duration::from_secs(1 << failures.min(5)).min(MAX_BACKOFF);
+        info!("reconnecting to {url} in {backoff:?}");
+        tokio::time::sleep(backoff).await;
+
+        match connect(&http, &url).await {
+            Ok(r) => response = Some(r),
+            Err(e) => warn!("could not reconnect to {url}: {e}"),
+        }
+    }
+}
+
+/// Forwards one connection's audio, returning how many bytes it carried.
+async fn stream(
+    response: reqwest::Response,
+    tx: &mpsc::Sender<Vec<u8>>,
+    title_tx: &watch::Sender<Option<String>>,
+) -> reqwest::Result<usize> {
+    let metaint = response
+        .headers()
+        .get("icy-metaint")
+        .and_then(|v| v.to_str().ok()?.parse().ok());
+    let mut icy = Icy::new(metaint);
+    let mut received = 0;
+
+    let mut body = response.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        let mut audio = Vec::with_capacity(chunk.len());
+        if let Some(title) = icy.feed(&chunk, &mut audio) {
+            title_tx.send_replace(Some(title));
+        }
+        received += audio.len();
+        if tx.send(audio).await.is_err() {
+            break;
+        }
+    }
+    Ok(received)
+}
+
+/// Splits the metadata blocks Icecast/SHOUTcast interleave every `metaint`
+/// bytes from the audio.
+struct Icy {
+    metaint: Option<usize>,
+    state: IcyState,
+    meta: Vec<u8>,
+}
+
+enum IcyState {
+    Audio(usize),
+    Length,
+    Meta(usize),
+}
+
+impl Icy {
+    fn new(metaint: Option<usize>) -> Self {
+        Self {
+            metaint,
+            state: IcyState::Audio(metaint.unwrap_or(0)),
+            meta: vec![],
+        }
+    }
+
+    /// Appends the audio in `chunk` to `audio`, returning the latest stream
+    /// title if a metadata block was completed.
+    fn feed(&mut self, mut chunk: &[u8], audio: &mut Vec<u8>) -> Option<String> {
+        let Some(metaint) = self.metaint else {
+            audio.extend_from_slice(chunk);
+            return None;
+        };
+
+        let mut title = None;
+        while !chunk.is_empty() {
+            match self.state {
+                IcyState::Audio(remaining) => {
+                    let n = remaining.min(chunk.len());
+                    audio.extend_from_slice(&chunk[..n]);
+                    chunk = &chunk[n..];
+                    self.state = match remaining - n {
+                        0 => IcyState::Length,
+                        left => IcyState::Audio(left),
+                    };
+                }
+                IcyState::Length => {
+                    let len = chunk[0] as usize * 16;
+                    chunk = &chunk[1..];
+                    self.meta.clear();
+                    self.state = match len {
+                        0 => IcyState::Audio(metaint),
+                        len => IcyState::Meta(len),
+                    };
+                }
+                IcyState::Meta(remaining) => {
+                    let n = remaining.min(chunk.len());
+                    self.meta.extend_from_slice(&chunk[..n]);
+                    chunk = &chunk[n..];
+                    if remaining == n {
+                        title = stream_title(&self.meta).or(title);
+                        self.state = IcyState::Audio(metaint);
+                    } else {
+                        self.state = IcyState::Meta(remaining - n);
+                    }
+                }
+            }
+        }
+        title
+    }
+}
+
+/// Pulls `StreamTitle` out of e.g. `StreamTitle='Artist - Song';StreamUrl='';`.
+fn stream_title(meta: &[u8]) -> Option<String> {
+    let meta = String::from_utf8_lossy(meta);
+    let start = meta.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &meta[start..];
+    let end = rest.find("';").unwrap_or(rest.trim_end_matches('\0').len());
+    let title = rest[..end].trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+/// Fed by [`pump`] and read through songbird's [`AsyncAdapterStream`], so
+/// waiting on the network happens on the runtime, not in the decoder.
+struct StreamReader {
+    rx: mpsc::Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl AsyncRead for StreamReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        while self.offset == self.pending.len() {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(chunk)) => {
+                    self.pending = chunk;
+                    self.offset = 0;
+                }
+                // Nothing read means the stream is over.
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        let n = buf.remaining().min(self.pending.len() - self.offset);
+        let offset = self.offset;
+        buf.put_slice(&self.pending[offset..offset + n]);
+        self.offset += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for StreamReader {
+    fn start_seek(self: Pin<&mut Self>, _pos: SeekFrom) -> std::io::Result<()> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Ok(0))
+    }
+}
+
+#[async_trait]
+impl AsyncMediaSource for StreamReader {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    async fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
@@ -1,3 +1,4 @@
+use dashmap::DashMap;
 use log::{error, info};
 use reqwest::Client as HttpClient;
 use serenity::{
@@ -12,8 +13,27 @@ use serenity::{
 use songbird::{Config, tracks::TrackHandle};
 use std::{collections::HashMap, sync::Arc};
 
+mod attachments;
+mod broadcast;
+mod cache;
 mod commands;
+mod ducking;
+mod history;
+mod library;
+mod live;
+mod pcm;
+mod playlists;
+mod queue;
+mod radio;
+mod search;
+mod segments;
+mod settings;
+mod soundboard;
+mod source;
+mod storage;
+mod tts;
 pub mod youtube;
+mod ytdlp;
 
 const COLOR_OK: u32 = 0xcba6f7;
 const COLOR_ERROR: u32 = 0xf38ba8;
@@ -35,7 +55,21 @@ impl EventHandler for Handler {
             Command::create_global_command(&ctx.http, commands::disconnect::register()).await,
             Command::create_global_command(&ctx.http, commands::pause::register()).await,
             Command::create_global_command(&ctx.http, commands::search::register()).await,
-            // Command::create_global_command(&ctx.http, commands::record::register()).await,
+            Command::create_global_command(&ctx.http, commands::record::register()).await,
+            Command::create_global_command(&ctx.http, commands::bridge::register()).await,
+            Command::create_global_command(&ctx.http, commands::broadcast::register()).await,
+            Command::create_global_command(&ctx.http, commands::library::register()).await,
+            Command::create_global_command(&ctx.http, commands::chapters::register()).await,
+            Command::create_global_command(&ctx.http, commands::chapter::register()).await,
+            Command::create_global_command(&ctx.http, commands::segments::register()).await,
+            Command::create_global_command(&ctx.http, commands::sound::register()).await,
+            Command::create_global_command(&ctx.http, commands::say::register()).await,
+            Command::create_global_command(&ctx.http, commands::tts::register()).await,
+            Command::create_global_command(&ctx.http, commands::duck::register()).await,
+            Command::create_global_command(&ctx.http, commands::radio::register()).await,
+            Command::create_global_command(&ctx.http, commands::history::register()).await,
+            Command::create_global_command(&ctx.http, commands::stats::register()).await,
+            Command::create_global_command(&ctx.http, commands::playlist::register()).await,
         ];
 
         info!("Created {} commands", commands.len());
@@ -47,7 +81,11 @@ impl EventHandler for Handler {
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::Command(command) = interaction {
+        if let Interaction::Autocomplete(autocomplete) = interaction {
+            commands::autocomplete::run(&ctx, &autocomplete)
+                .await
+                .unwrap();
+        } else if let Interaction::Command(command) = interaction {
             // println!("Received command interaction: {command:#?}");
 
             match command.data.name.as_str() {
@@ -72,9 +110,51 @@ impl EventHandler for Handler {
                 "search" => {
                     commands::search::run(&ctx, &command).await.unwrap();
                 }
-                // "record" => {
-                //     commands::record::run(&ctx, &command).await.unwrap();
-                // }
+                "record" => {
+                    commands::record::run(&ctx, &command).await.unwrap();
+                }
+                "bridge" => {
+                    commands::bridge::run(&ctx, &command).await.unwrap();
+                }
+                "broadcast" => {
+                    commands::broadcast::run(&ctx, &command).await.unwrap();
+                }
+                "library" => {
+                    commands::library::run(&ctx, &command).await.unwrap();
+                }
+                "chapters" => {
+                    commands::chapters::run(&ctx, &command).await.unwrap();
+                }
+                "chapter" => {
+                    commands::chapter::run(&ctx, &command).await.unwrap();
+                }
+                "segments" => {
+                    commands::segments::run(&ctx, &command).await.unwrap();
+                }
+                "sound" => {
+                    commands::sound::run(&ctx, &command).await.unwrap();
+                }
+                "say" => {
+                    commands::say::run(&ctx, &command).await.unwrap();
+                }
+                "tts" => {
+                    commands::tts::run(&ctx, &command).await.unwrap();
+                }
+                "duck" => {
+                    commands::duck::run(&ctx, &command).await.unwrap();
+                }
+                "radio" => {
+                    commands::radio::run(&ctx, &command).await.unwrap();
+                }
+                "history" => {
+                    commands::history::run(&ctx, &command).await.unwrap();
+                }
+                "stats" => {
+                    commands::stats::run(&ctx, &command).await.unwrap();
+                }
+                "playlist" => {
+                    commands::playlist::run(&ctx, &command).await.unwrap();
+                }
                 _ => {}
             };
 
@@ -86,10 +166,21 @@ impl EventHandler for Handler {
             //     }
             // }
         } else if let Interaction::Component(component) = interaction {
-            if component.data.custom_id.as_str() == "select_search" {
+            let custom_id = component.data.custom_id.as_str();
+            if custom_id.starts_with(commands::search::SELECT_PREFIX) {
                 commands::play::run_component(&ctx, &component)
                     .await
                     .unwrap();
+            } else if custom_id.starts_with(commands::search::PAGE_PREFIX) {
+                commands::search::run_component(&ctx, &component)
+                    .await
+                    .unwrap();
+            } else if custom_id.starts_with(commands::history::PAGE_PREFIX)
+                || custom_id.starts_with(commands::history::QUEUE_PREFIX)
+            {
+                commands::history::run_component(&ctx, &component)
+                    .await
+                    .unwrap();
             }
         }
     }
@@ -99,6 +190,47 @@ struct UserData {
     http: HttpClient,
     songbird: Arc<songbird::Songbird>,
     track_handles: HashMap<GuildId, TrackHandle>,
+    queues: HashMap<GuildId, queue::Queue>,
+    /// Most recently played first, see [`queue::play`].
+    recent: HashMap<GuildId, queue::Queue>,
+    /// The next track per guild, buffered before the current one ends.
+    prepared: HashMap<GuildId, queue::Prepared>,
+    radios: HashMap<GuildId, radio::Radio>,
+    suggestions: Arc<commands::autocomplete::Suggestions>,
+    recordings: HashMap<GuildId, commands::record::Receiver>,
+    bridges: HashMap<GuildId, Arc<commands::bridge::Bridge>>,
+    storage: Option<Arc<storage::S3Storage>>,
+    broadcasts: broadcast::Broadcasts,
+    search: Arc<dyn search::SearchProvider>,
+    library: Option<Arc<library::Library>>,
+    attachments: Arc<attachments::AttachmentCache>,
+    settings: Arc<settings::Settings>,
+    history: Arc<history::History>,
+    playlists: Arc<playlists::Playlists>,
+    segments: Arc<dyn segments::SegmentProvider>,
+    soundboard: Arc<soundboard::Soundboard>,
+    tts: Arc<tts::Tts>,
+    volumes: DashMap<GuildId, Arc<ducking::Volume>>,
+}
+
+impl UserData {
+    /// Whether something needs to hear the voice channel, so the bot must
+    /// stay undeafened.
+    fn is_listening(&self, guild_id: GuildId) -> bool {
+        self.recordings.contains_key(&guild_id)
+            || self.bridges.contains_key(&guild_id)
+            || self
+                .broadcasts
+                .lock()
+                .unwrap()
+                .get(&guild_id)
+                .is_some_and(|b| b.source == broadcast::Source::Voice)
+            || self.settings.get(guild_id).auto_duck
+    }
+
+    fn volume(&self, guild_id: GuildId) -> Arc<ducking::Volume> {
+        self.volumes.entry(guild_id).or_default().clone()
+    }
 }
 
 impl TypeMapKey for UserData {
@@ -120,12 +252,43 @@ async fn main() -> anyhow::Result<()> {
     let manager = songbird::Songbird::serenity_from_config(
         Config::default().decode_mode(songbird::driver::DecodeMode::Decode),
     );
+    let http = HttpClient::new();
     let user_data = UserData {
-        http: HttpClient::new(),
+        storage: storage::S3Storage::from_env(http.clone()).map(Arc::new),
+        search: search::from_env(http.clone()).into(),
+        library: library::Library::from_env(),
+        attachments: Arc::new(attachments::AttachmentCache::from_env()),
+        settings: Arc::new(settings::Settings::load()),
+        history: Arc::new(history::History::load()),
+        playlists: Arc::new(playlists::Playlists::load()),
+        segments: segments::from_env(http.clone()),
+        soundboard: Arc::new(soundboard::Soundboard::load()),
+        tts: Arc::new(tts::Tts::from_env()),
+        http,
         songbird: Arc::clone(&manager),
         track_handles: HashMap::new(),
+        queues: HashMap::new(),
+        recent: HashMap::new(),
+        prepared: HashMap::new(),
+        radios: HashMap::new(),
+        suggestions: Arc::default(),
+        recordings: HashMap::new(),
+        bridges: HashMap::new(),
+        broadcasts: broadcast::Broadcasts::default(),
+        volumes: DashMap::new(),
     };
 
+    if let Ok(addr) = std::env::var("BROADCAST_HTTP_ADDR") {
+        let broadcasts = user_data.broadcasts.clone();
+        tokio::spawn(async move {
+            if let Err(e) = broadcast::serve(addr, broadcasts).await {
+                error!("Broadcast server stopped: {e:?}");
+            }
+        });
+    }
+
+    tokio::spawn(ytdlp::config().check_version());
+
     let token = std::env::var("BOT_TOKEN")?;
 
     let intents =
@@ -0,0 +1,152 @@
+use std::{
+    collections::VecDeque,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    sync::{Arc, Mutex},
+};
+
+use songbird::{
+    events::context_data::VoiceTick,
+    input::{Input, RawAdapter},
+};
+use symphonia::core::io::MediaSource;
+
+pub const SAMPLE_RATE: u32 = 48_000;
+pub const CHANNELS: u32 = 2;
+/// One 20ms voice frame worth of interleaved stereo samples.
+pub const FRAME_SAMPLES: usize = (SAMPLE_RATE / 50 * CHANNELS) as usize;
+
+/// Anything beyond this is dropped so a stalled reader can't build up latency.
+const MAX_BUFFERED: usize = FRAME_SAMPLES * 25;
+/// Samples held back before playback (re)starts, to ride out network jitter.
+const PRIME_SAMPLES: usize = FRAME_SAMPLES * 2;
+
+/// A queue of live 48kHz stereo PCM, fed by received voice and drained by a
+/// songbird track created with [`PcmBuffer::input`].
+#[derive(Clone, Default)]
+pub struct PcmBuffer {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    samples: VecDeque<f32>,
+    primed: bool,
+}
+
+impl PcmBuffer {
+    /// Appends decoded voice as received in a `VoiceTick`.
+    pub fn push_i16(&self, samples: &[i16], volume: f32) {
+        self.push(samples.iter().map(|s| *s as f32 / 32768.0 * volume));
+    }
+
+    pub fn push_f32(&self, samples: &[f32], volume: f32) {
+        self.push(samples.iter().map(|s| s * volume));
+    }
+
+    /// How many samples are waiting to be read.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().samples.len()
+    }
+
+    fn push(&self, samples: impl Iterator<Item = f32>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.samples.extend(samples);
+
+        let excess = inner.samples.len().saturating_sub(MAX_BUFFERED);
+        inner.samples.drain(..excess);
+        if inner.samples.len() >= PRIME_SAMPLES {
+            inner.primed = true;
+        }
+    }
+
+    /// A never-ending track playing whatever is pushed into this buffer.
+    pub fn input(&self) -> Input {
+        RawAdapter::new(
+            PcmSource {
+                buffer: self.clone(),
+            },
+            SAMPLE_RATE,
+            CHANNELS,
+        )
+        .into()
+    }
+}
+
+/// Sums the decoded voice of everyone speaking in a tick.
+pub fn mix_voice(tick: &VoiceTick) -> Vec<i16> {
+    let mut mix: Vec<i16> = vec![];
+    for decoded in tick
+        .speaking
+        .values()
+        .filter_map(|d| d.decoded_voice.as_ref())
+    {
+        if mix.len() < decoded.len() {
+            mix.resize(decoded.len(), 0);
+        }
+        for (m, s) in mix.iter_mut().zip(decoded) {
+            *m = m.saturating_add(*s);
+        }
+    }
+    mix
+}
+
+/// Raw `f32` reader handed to songbird. It never blocks the mixer: when no
+/// voice is queued it hands out a frame of silence instead.
+struct PcmSource {
+    buffer: PcmBuffer,
+}
+
+impl PcmBuffer {
+    /// Takes up to `out.len()` samples. When nothing is queued, `out` is
+    /// filled with silence instead so that readers never stall.
+    pub fn read_frame(&self, out: &mut [f32]) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+
+        let available = if inner.primed {
+            inner.samples.len().min(out.len())
+        } else {
+            0
+        };
+        if available == 0 {
+            inner.primed = false;
+            out.fill(0.0);
+            return out.len();
+        }
+
+        for (o, sample) in out.iter_mut().zip(inner.samples.drain(..available)) {
+            *o = sample;
+        }
+        available
+    }
+}
+
+impl Read for PcmSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Symphonia reads one block per fetch, so capping each read at a
+        // single frame keeps the reader in step with the 20ms mixer tick.
+        let mut frame = [0f32; FRAME_SAMPLES];
+        let wanted = (buf.len() / 4).min(FRAME_SAMPLES);
+        let read = self.buffer.read_frame(&mut frame[..wanted]);
+
+        for (chunk, sample) in buf.chunks_exact_mut(4).zip(&frame[..read]) {
+            chunk.copy_from_slice(&sample.to_le_bytes());
+        }
+        Ok(read * 4)
+    }
+}
+
+impl Seek for PcmSource {
+    fn seek(&mut self, _pos: SeekFrom) -> std::io::Result<u64> {
+        Err(ErrorKind::Unsupported.into())
+    }
+}
+
+impl MediaSource for PcmSource {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
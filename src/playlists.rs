@@ -0,0 +1,239 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use anyhow::{Context, bail};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serenity::all::{GuildId, UserId};
+
+const MAX_NAME_CHARS: usize = 32;
+const MAX_TRACKS: usize = 500;
+
+/// Saved lists of tracks, each owned by whoever made it, in `playlists.json`
+/// under the data directory.
+pub struct Playlists {
+    path: PathBuf,
+    lists: Mutex<HashMap<UserId, BTreeMap<String, Playlist>>>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Playlist {
+    pub tracks: Vec<PlaylistTrack>,
+    /// Guilds where anyone may play it, not just the owner.
+    pub shared_in: Vec<GuildId>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlaylistTrack {
+    pub url: String,
+    pub title: String,
+}
+
+impl Playlists {
+    pub fn load() -> Self {
+        let path = crate::settings::dir().join("playlists.json");
+        let lists = std::fs::read(&path)
+            .ok()
+            .and_then(|b| serde_json::from_slice(&b).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            lists: Mutex::new(lists),
+        }
+    }
+
+    /// Names of `user`'s own playlists, sorted.
+    pub fn names(&self, user: UserId) -> Vec<String> {
+        let lists = self.lists.lock().unwrap();
+        lists
+            .get(&user)
+            .map(|l| l.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Names of playlists others shared in `guild_id`, with their owners.
+    pub fn shared(&self, guild_id: GuildId, except: UserId) -> Vec<(UserId, String)> {
+        let lists = self.lists.lock().unwrap();
+        lists
+            .iter()
+            .filter(|(owner, _)| **owner != except)
+            .flat_map(|(owner, l)| {
+                l.iter()
+                    .filter(|(_, p)| p.shared_in.contains(&guild_id))
+                    .map(|(name, _)| (*owner, name.clone()))
+            })
+            .collect()
+    }
+
+    /// Tracks whose title contains `needle` (lowercase), from `user`'s own
+    /// playlists and those shared in `guild_id`.
+    pub fn tracks_matching(
+        &self,
+        user: UserId,
+        guild_id: Option<GuildId>,
+        needle: &str,
+    ) -> Vec<PlaylistTrack> {
+        let lists = self.lists.lock().unwrap();
+        lists
+            .iter()
+            .flat_map(|(owner, l)| {
+                l.values()
+                    .filter(move |p| {
+                        *owner == user || guild_id.is_some_and(|g| p.shared_in.contains(&g))
+                    })
+                    .flat_map(|p| &p.tracks)
+            })
+            .filter(|t| t.title.to_lowercase().contains(needle))
+            .cloned()
+            .collect()
+    }
+
+    /// The playlist `choice` names, with its owner and plain name: as
+    /// `<owner id>:<name>` the way autocomplete offers others' playlists,
+    /// else `user`'s own called `choice`, else one shared in `guild_id`.
+    pub fn find(
+        &self,
+        user: UserId,
+        guild_id: GuildId,
+        choice: &str,
+    ) -> Option<(UserId, String, Playlist)> {
+        let lists = self.lists.lock().unwrap();
+        let visible =
+            |owner: &UserId, p: &Playlist| *owner == user || p.shared_in.contains(&guild_id);
+        if let Some((owner, name)) = choice.split_once(':')
+            && let Some(owner) = owner.parse().ok().filter(|&id| id != 0).map(UserId::new)
+            && let Some(playlist) = lists.get(&owner).and_then(|l| l.get(name))
+            && visible(&owner, playlist)
+        {
+            return Some((owner, name.to_string(), playlist.clone()));
+        }
+        if let Some(playlist) = lists.get(&user).and_then(|l| l.get(choice)) {
+            return Some((user, choice.to_string(), playlist.clone()));
+        }
+        lists.iter().find_map(|(owner, l)| {
+            l.get(choice)
+                .filter(|p| visible(owner, p))
+                .map(|p| (*owner, choice.to_string(), p.clone()))
+        })
+    }
+
+    pub fn create(&self, user: UserId, name: &str) -> anyhow::Result<()> {
+        check_name(name)?;
+        self.update(user, |lists| {
+            if lists.contains_key(name) {
+                bail!("you already have a playlist called {name}");
+            }
+            lists.insert(name.to_string(), Playlist::default());
+            Ok(())
+        })
+    }
+
+    /// Adds `track` at the end, returning its position from 1.
+    pub fn add(&self, user: UserId, name: &str, track: PlaylistTrack) -> anyhow::Result<usize> {
+        self.update(user, |lists| {
+            let playlist = own(lists, name)?;
+            if playlist.tracks.len() >= MAX_TRACKS {
+                bail!("playlists can hold at most {MAX_TRACKS} tracks");
+            }
+            playlist.tracks.push(track);
+            Ok(playlist.tracks.len())
+        })
+    }
+
+    /// Takes out the track at `position`, counting from 1.
+    pub fn remove(
+        &self,
+        user: UserId,
+        name: &str,
+        position: usize,
+    ) -> anyhow::Result<PlaylistTrack> {
+        self.update(user, |lists| {
+            let playlist = own(lists, name)?;
+            if position == 0 || position > playlist.tracks.len() {
+                bail!("{name} has {} tracks", playlist.tracks.len());
+            }
+            Ok(playlist.tracks.remove(position - 1))
+        })
+    }
+
+    pub fn delete(&self, user: UserId, name: &str) -> anyhow::Result<()> {
+        self.update(user, |lists| {
+            lists
+                .remove(name)
+                .map(|_| ())
+                .with_context(|| format!("you have no playlist called {name}"))
+        })
+    }
+
+    pub fn rename(&self, user: UserId, name: &str, new_name: &str) -> anyhow::Result<()> {
+        check_name(new_name)?;
+        self.update(user, |lists| {
+            if lists.contains_key(new_name) {
+                bail!("you already have a playlist called {new_name}");
+            }
+            let playlist = lists
+                .remove(name)
+                .with_context(|| format!("you have no playlist called {name}"))?;
+            lists.insert(new_name.to_string(), playlist);
+            Ok(())
+        })
+    }
+
+    /// Lets everyone in `guild_id` play it, or only the owner again.
+    pub fn share(
+        &self,
+        user: UserId,
+        name: &str,
+        guild_id: GuildId,
+        shared: bool,
+    ) -> anyhow::Result<()> {
+        self.update(user, |lists| {
+            let playlist = own(lists, name)?;
+            playlist.shared_in.retain(|g| *g != guild_id);
+            if shared {
+                playlist.shared_in.push(guild_id);
+            }
+            Ok(())
+        })
+    }
+
+    /// Changes `user`'s playlists and saves them all, unless `f` fails.
+    fn update<T>(
+        &self,
+        user: UserId,
+        f: impl FnOnce(&mut BTreeMap<String, Playlist>) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut lists = self.lists.lock().unwrap();
+        let result = f(lists.entry(user).or_default())?;
+
+        let write = || -> std::io::Result<()> {
+            if let Some(dir) = self.path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(&self.path, serde_json::to_vec(&*lists)?)
+        };
+        if let Err(e) = write() {
+            warn!("could not save playlists: {e}");
+        }
+        Ok(result)
+    }
+}
+
+fn own<'a>(
+    lists: &'a mut BTreeMap<String, Playlist>,
+    name: &str,
+) -> anyhow::Result<&'a mut Playlist> {
+    lists
+        .get_mut(name)
+        .with_context(|| format!("you have no playlist called {name}"))
+}
+
+fn check_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        bail!("playlist names are 1 to {MAX_NAME_CHARS} characters");
+    }
+    Ok(())
+}
@@ -0,0 +1,635 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use log::{debug, warn};
+use reqwest::Client as HttpClient;
+use serenity::{
+    all::{ChannelId, Colour, CreateEmbed, CreateMessage, GuildId, Timestamp, UserId},
+    async_trait,
+    prelude::*,
+};
+use songbird::{
+    Event, EventContext, EventHandler, TrackEvent,
+    input::{
+        Input, LiveInput,
+        codecs::{get_codec_registry, get_probe},
+    },
+    tracks::{PlayMode, Track, TrackHandle},
+};
+use url::Url;
+
+use crate::{
+    COLOR_ERROR, UserData,
+    commands::play::TrackMeta,
+    history::Play,
+    live::Live,
+    source::{Playable, Source},
+};
+
+/// Tracks waiting to play after the current one, per guild.
+pub type Queue = VecDeque<QueueEntry>;
+
+/// How many recently played tracks are remembered per guild.
+const RECENT_LEN: usize = 50;
+/// How long before the current track ends the next one gets buffered.
+const PREPARE_AHEAD: Duration = Duration::from_secs(15);
+const PREPARE_POLL: Duration = Duration::from_secs(1);
+/// Retries of a failed track before looking for another source, waiting
+/// twice as long each time.
+const MAX_RETRIES: u32 = 2;
+const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+
+#[derive(Clone)]
+pub struct QueueEntry {
+    pub url: Url,
+    pub title: String,
+    /// The text channel it was asked for in.
+    pub requested_in: ChannelId,
+    /// `None` for radio picks.
+    pub requested_by: Option<UserId>,
+}
+
+/// How a track came to be playing, to decide what to do when it fails.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Attempt {
+    /// Played as asked, after this many retries.
+    Retry(u32),
+    /// Found by searching for the title of a track that kept failing.
+    Fallback,
+}
+
+/// The next queued track, already resolved and probed so it starts without
+/// waiting on yt-dlp or the network.
+pub struct Prepared {
+    url: Url,
+    /// Only to be `Sync`, it's taken out once.
+    input: Mutex<Input>,
+    duration: Option<Duration>,
+    source: Source,
+}
+
+/// Plays `entry` right away, replacing whatever is playing. Fails when the
+/// bot isn't in a voice channel in this guild.
+pub async fn play(
+    ctx: &Context,
+    guild_id: GuildId,
+    entry: QueueEntry,
+) -> Result<TrackHandle, String> {
+    start(ctx, guild_id, entry, Attempt::Retry(0), None).await
+}
+
+/// Plays `entry` in place of `current`, unless something else started
+/// while it was being opened.
+pub async fn play_after(
+    ctx: &Context,
+    guild_id: GuildId,
+    entry: QueueEntry,
+    current: &TrackHandle,
+) -> Result<TrackHandle, String> {
+    start(ctx, guild_id, entry, Attempt::Retry(0), Some(current)).await
+}
+
+/// Opening `entry` can take seconds of network probing, so the data lock is
+/// only taken around it, not across.
+async fn start(
+    ctx: &Context,
+    guild_id: GuildId,
+    entry: QueueEntry,
+    attempt: Attempt,
+    after: Option<&TrackHandle>,
+) -> Result<TrackHandle, String> {
+    let not_in_call = || "Not in a voice channel here".to_string();
+    let (http, prepared) = {
+        let mut typemap = ctx.data.write().await;
+        let data = typemap.get_mut::<UserData>().unwrap();
+        if data.songbird.get(guild_id).is_none() {
+            return Err(not_in_call());
+        }
+        if let Ok(path) = entry.url.to_file_path() {
+            data.attachments.used(&path);
+        }
+        (data.http.clone(), data.prepared.remove(&guild_id))
+    };
+    let (playable, duration) = match prepared {
+        Some(prepared) if prepared.url == entry.url => {
+            debug!("{} was prepared ahead", entry.url);
+            let playable = Playable {
+                input: prepared.input.into_inner().unwrap(),
+                live: None,
+                source: prepared.source,
+            };
+            (playable, prepared.duration)
+        }
+        _ => (crate::source::resolve(&http, &entry.url).await, None),
+    };
+
+    let mut typemap = ctx.data.write().await;
+    let data = typemap.get_mut::<UserData>().unwrap();
+    let handler_lock = data.songbird.get(guild_id).ok_or_else(not_in_call)?;
+    if let Some(after) = after
+        && data.track_handles.get(&guild_id).map(|t| t.uuid()) != Some(after.uuid())
+    {
+        return Err("Something else started meanwhile".to_string());
+    }
+    let mut handler = handler_lock.lock().await;
+
+    if let Some(track) = data.track_handles.get(&guild_id) {
+        let _ = track.stop();
+    }
+
+    let recent = data.recent.entry(guild_id).or_default();
+    recent.retain(|e| e.url != entry.url);
+    recent.push_front(entry.clone());
+    recent.truncate(RECENT_LEN);
+
+    let live = playable.live;
+    let meta = TrackMeta {
+        // A station's own name beats its URL.
+        title: match &live {
+            Some(Live {
+                name: Some(name), ..
+            }) if entry.title == entry.url.as_str() => name.clone(),
+            _ => entry.title,
+        },
+        url: entry.url,
+        stream_title: live.map(|l| l.title),
+        requested_in: entry.requested_in,
+        requested_by: entry.requested_by,
+        started_at: Timestamp::now().unix_timestamp(),
+        attempt,
+        chapters: OnceLock::new(),
+    };
+    let is_live = meta.is_live();
+    let song = handler.play(Track::new_with_data(playable.input, Arc::new(meta)));
+
+    // Loops by default, but only once nothing else is waiting, otherwise the
+    // queue would never move on. Live streams never end, so never loop. The
+    // radio picks something new instead.
+    // TODO: persist loop setting
+    if !is_live
+        && !data.radios.contains_key(&guild_id)
+        && data.queues.get(&guild_id).is_none_or(|q| q.is_empty())
+    {
+        let _ = song.enable_loop();
+    }
+    data.volume(guild_id).set_track(song.clone());
+    for event in [TrackEvent::End, TrackEvent::Error] {
+        let _ = song.add_event(
+            event.into(),
+            Advance {
+                ctx: ctx.clone(),
+                guild_id,
+            },
+        );
+    }
+    if !is_live {
+        let (ctx, http, song) = (ctx.clone(), data.http.clone(), song.clone());
+        let extract = playable.source == Source::Extract;
+        let categories = data.settings.get(guild_id).skip_categories;
+        if extract
+            && !categories.is_empty()
+            && let Some(video_id) = crate::youtube::video_id(&song.data::<TrackMeta>().url)
+        {
+            tokio::spawn(crate::segments::watch(
+                data.segments.clone(),
+                song.clone(),
+                video_id,
+                categories,
+            ));
+        }
+        tokio::spawn(async move {
+            let mut duration = duration;
+            if extract {
+                let meta = song.data::<TrackMeta>();
+                match crate::ytdlp::config().info(meta.url.as_str()).await {
+                    Ok(info) => {
+                        duration = duration.or(info.duration);
+                        let _ = meta.chapters.set(info.chapters);
+                    }
+                    Err(e) => warn!("no chapters for {}: {e}", meta.url),
+                }
+            }
+            prepare_next(ctx, http, guild_id, song, duration).await;
+        });
+    }
+    let settings = data.settings.get(guild_id);
+    if settings.announce && attempt == Attempt::Retry(0) {
+        let (tts, call, volume) = (
+            data.tts.clone(),
+            handler_lock.clone(),
+            data.volume(guild_id),
+        );
+        let text = format!("Now playing {}", song.data::<TrackMeta>().title)
+            .chars()
+            .take(tts.max_chars)
+            .collect::<String>();
+        tokio::spawn(async move {
+            match tts.synthesize(&text, settings.tts_voice.as_deref()).await {
+                Ok(speech) => crate::tts::speak(call, volume, speech).await,
+                Err(e) => warn!("could not announce track: {e:#}"),
+            }
+        });
+    }
+    data.track_handles.insert(guild_id, song.clone());
+    Ok(song)
+}
+
+/// Adds `entries` after everything already queued, in order, and returns
+/// where each one ended up: 0 for a track that started playing straight away
+/// because nothing was playing, 1 for the next up and so on. When nothing
+/// was playing and that can't change, none of them are queued and each gets
+/// the error.
+pub async fn enqueue(
+    ctx: &Context,
+    guild_id: GuildId,
+    entries: Vec<QueueEntry>,
+) -> Vec<Result<usize, String>> {
+    let count = entries.len();
+    let mut positions: Vec<Result<usize, String>> = vec![];
+    let (first, before) = {
+        let mut typemap = ctx.data.write().await;
+        let data = typemap.get_mut::<UserData>().unwrap();
+        let playing = match data.track_handles.get(&guild_id) {
+            Some(track) => track.get_info().await.is_ok_and(|i| !i.playing.is_done()),
+            None => false,
+        };
+
+        let mut entries = entries.into_iter();
+        let first = if playing { None } else { entries.next() };
+
+        let queue = data.queues.entry(guild_id).or_default();
+        let before = queue.len();
+        for entry in entries {
+            queue.push_back(entry);
+            positions.push(Ok(queue.len()));
+        }
+
+        let Some(first) = first else {
+            if let Some(track) = data.track_handles.get(&guild_id) {
+                // Let a looping track finish so the queue gets its turn.
+                let _ = track.disable_loop();
+            }
+            return positions;
+        };
+        (first, before)
+    };
+
+    match play(ctx, guild_id, first).await {
+        Ok(_) => positions.insert(0, Ok(0)),
+        Err(e) => {
+            // Nothing would ever get to the rest.
+            let mut typemap = ctx.data.write().await;
+            let data = typemap.get_mut::<UserData>().unwrap();
+            if let Some(queue) = data.queues.get_mut(&guild_id) {
+                queue.truncate(before);
+            }
+            return vec![Err(e); count];
+        }
+    }
+    positions
+}
+
+/// The error to show when none of `positions` made it into the queue.
+pub fn failed(positions: &[Result<usize, String>]) -> Option<String> {
+    match positions.first() {
+        Some(Err(e)) if positions.iter().all(Result::is_err) => Some(e.clone()),
+        _ => None,
+    }
+}
+
+/// A markdown link per entry, for [`positions`].
+pub fn describe(entries: &[QueueEntry]) -> Vec<String> {
+    entries
+        .iter()
+        .map(|e| match e.url.scheme() {
+            // Local files can't be linked to.
+            "file" => e.title.clone(),
+            _ => format!("[{}]({})", e.title, e.url),
+        })
+        .collect()
+}
+
+/// Lists where each of `lines` ended up after [`enqueue`], cut short to fit
+/// in an embed.
+pub fn positions(positions: &[Result<usize, String>], lines: Vec<String>) -> String {
+    const MAX_LINES: usize = 20;
+    let mut description = positions
+        .iter()
+        .zip(lines)
+        .take(MAX_LINES)
+        .map(|(position, line)| match position {
+            Ok(0) => format!("Now playing: {line}"),
+            Ok(n) => format!("`#{n}` {line}"),
+            Err(e) => format!("Could not queue {line}: {e}"),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if positions.len() > MAX_LINES {
+        description.push_str(&format!("\n...and {} more", positions.len() - MAX_LINES));
+    }
+    description
+}
+
+/// Drops everything waiting to play in a guild, and stops the radio.
+pub fn clear(data: &mut UserData, guild_id: GuildId) {
+    data.queues.remove(&guild_id);
+    data.prepared.remove(&guild_id);
+    data.radios.remove(&guild_id);
+}
+
+/// Buffers whatever is next in the queue once `current` is in its last
+/// seconds, or straight away when its length isn't known. Keeps watching
+/// until `current` is done, since the queue can change meanwhile.
+async fn prepare_next(
+    ctx: Context,
+    http: HttpClient,
+    guild_id: GuildId,
+    current: TrackHandle,
+    duration: Option<Duration>,
+) {
+    let mut failed: Option<Url> = None;
+    loop {
+        tokio::time::sleep(PREPARE_POLL).await;
+        let Ok(info) = current.get_info().await else {
+            return;
+        };
+        if info.playing.is_done() {
+            return;
+        }
+        if duration.is_some_and(|d| d.saturating_sub(info.position) > PREPARE_AHEAD) {
+            continue;
+        }
+
+        let next = {
+            let typemap = ctx.data.read().await;
+            let data = typemap.get::<UserData>().unwrap();
+            if data.track_handles.get(&guild_id).map(|t| t.uuid()) != Some(current.uuid()) {
+                return;
+            }
+            let Some(next) = data.queues.get(&guild_id).and_then(|q| q.front()) else {
+                continue;
+            };
+            let ready = data
+                .prepared
+                .get(&guild_id)
+                .is_some_and(|p| p.url == next.url);
+            if ready || failed.as_ref() == Some(&next.url) {
+                continue;
+            }
+            next.url.clone()
+        };
+
+        match prepare(&http, &next).await {
+            Some(prepared) => {
+                let mut typemap = ctx.data.write().await;
+                let data = typemap.get_mut::<UserData>().unwrap();
+                // Only worth keeping if it's still next.
+                if data
+                    .queues
+                    .get(&guild_id)
+                    .and_then(|q| q.front())
+                    .is_some_and(|e| e.url == next)
+                {
+                    data.prepared.insert(guild_id, prepared);
+                }
+            }
+            None => failed = Some(next),
+        }
+    }
+}
+
+/// Resolves and probes `url`. Live streams aren't worth buffering early and
+/// get `None`, like anything that fails to open.
+async fn prepare(http: &HttpClient, url: &Url) -> Option<Prepared> {
+    let (source, resolved, _) = Source::detect(http, url).await;
+    if matches!(source, Source::Live | Source::Hls) {
+        return None;
+    }
+    let playable = source.open(http.clone(), &resolved, None).await;
+    let input = match playable
+        .input
+        .make_playable_async(get_codec_registry(), get_probe())
+        .await
+    {
+        Ok(input) => input,
+        Err(e) => {
+            warn!("could not prepare {url}: {e}");
+            return None;
+        }
+    };
+    debug!("prepared {url}");
+    let duration = match &input {
+        Input::Live(LiveInput::Parsed(parsed), _) => parsed.format.default_track().and_then(|t| {
+            let params = &t.codec_params;
+            let time = params.time_base?.calc_time(params.n_frames?);
+            Some(Duration::from_secs_f64(time.seconds as f64 + time.frac))
+        }),
+        _ => None,
+    };
+    Some(Prepared {
+        url: url.clone(),
+        input: Mutex::new(input),
+        duration,
+        source,
+    })
+}
+
+/// Starts the next queued track once the current one ends, or tries to
+/// recover the current one when it fails. Whatever ended is added to the
+/// guild's history, unless it failed.
+struct Advance {
+    ctx: Context,
+    guild_id: GuildId,
+}
+
+#[async_trait]
+impl EventHandler for Advance {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
+
+        let mut typemap = self.ctx.data.write().await;
+        let data = typemap.get_mut::<UserData>().unwrap();
+
+        let plays: Vec<Play> = tracks
+            .iter()
+            .filter(|(state, _)| !matches!(state.playing, PlayMode::Errored(_)))
+            .map(|(state, handle)| {
+                let meta = handle.data::<TrackMeta>();
+                Play {
+                    url: meta.url.to_string(),
+                    title: meta.title.clone(),
+                    requested_by: meta.requested_by,
+                    started_at: meta.started_at,
+                    listened: state.play_time,
+                }
+            })
+            .collect();
+        if !plays.is_empty() {
+            let (history, guild_id) = (data.history.clone(), self.guild_id);
+            tokio::task::spawn_blocking(move || {
+                for play in &plays {
+                    history.record(guild_id, play);
+                }
+            });
+        }
+
+        // Tracks replaced by `play` end too, only the current one counts.
+        let current = data.track_handles.get(&self.guild_id)?.uuid();
+        let (state, handle) = tracks.iter().find(|(_, handle)| handle.uuid() == current)?;
+        let (ctx, guild_id, handle) = (self.ctx.clone(), self.guild_id, (*handle).clone());
+        // Songbird runs a call's events one after another, so anything slow
+        // happens on its own task rather than holding up voice ticks.
+        if let PlayMode::Errored(e) = &state.playing {
+            let error = e.to_string();
+            drop(typemap);
+            tokio::spawn(async move { recover(&ctx, guild_id, handle, error).await });
+            return None;
+        }
+
+        let next = data.queues.get_mut(&guild_id).and_then(|q| q.pop_front());
+        let radio = data.radios.contains_key(&guild_id);
+        drop(typemap);
+        tokio::spawn(async move {
+            match next {
+                Some(next) => {
+                    if let Err(e) = play_after(&ctx, guild_id, next, &handle).await {
+                        warn!("could not play the next track: {e}");
+                    }
+                }
+                None if radio => crate::radio::advance(&ctx, guild_id, handle).await,
+                None => {}
+            }
+        });
+        None
+    }
+}
+
+/// Retries a failed track with backoff, then tries the first search result
+/// for its title, then gives up on it and moves on to the next one. Each
+/// step is reported where the track was asked for.
+async fn recover(ctx: &Context, guild_id: GuildId, track: TrackHandle, error: String) {
+    let meta = track.data::<TrackMeta>();
+    warn!("track {} failed: {error}", meta.url);
+    let entry = QueueEntry {
+        url: meta.url.clone(),
+        title: meta.title.clone(),
+        requested_in: meta.requested_in,
+        requested_by: meta.requested_by,
+    };
+    let failed = format!(
+        "Could not play {}: {error}",
+        describe(std::slice::from_ref(&entry)).concat()
+    );
+    let report = |action: String| report(ctx, entry.requested_in, format!("{failed}\n{action}"));
+
+    let retry = match meta.attempt {
+        Attempt::Retry(n) if n < MAX_RETRIES => {
+            let backoff = RETRY_BACKOFF * 2u32.pow(n);
+            report(format!(
+                "Retrying in {}s ({} of {MAX_RETRIES})",
+                backoff.as_secs(),
+                n + 1
+            ))
+            .await;
+            tokio::time::sleep(backoff).await;
+            Some((entry.clone(), Attempt::Retry(n + 1)))
+        }
+        Attempt::Retry(_) => alternative(ctx, &entry)
+            .await
+            .map(|alt| (alt, Attempt::Fallback)),
+        Attempt::Fallback => None,
+    };
+
+    let (next, radio) = {
+        let mut typemap = ctx.data.write().await;
+        let data = typemap.get_mut::<UserData>().unwrap();
+        // Something else may have been played meanwhile.
+        if data.track_handles.get(&guild_id).map(|t| t.uuid()) != Some(track.uuid()) {
+            return;
+        }
+        let next = match retry {
+            Some(_) => None,
+            None => data.queues.get_mut(&guild_id).and_then(|q| q.pop_front()),
+        };
+        (next, data.radios.contains_key(&guild_id))
+    };
+    let mut pick = false;
+    let action = match retry {
+        Some((alt, Attempt::Fallback)) => {
+            let action = format!(
+                "Trying {} instead",
+                describe(std::slice::from_ref(&alt)).concat()
+            );
+            match start(ctx, guild_id, alt, Attempt::Fallback, Some(&track)).await {
+                Ok(_) => action,
+                Err(e) => e,
+            }
+        }
+        Some((entry, attempt)) => {
+            if let Err(e) = start(ctx, guild_id, entry, attempt, Some(&track)).await {
+                warn!("could not retry {}: {e}", meta.url);
+            }
+            return;
+        }
+        None => match next {
+            Some(next) => match play_after(ctx, guild_id, next, &track).await {
+                Ok(_) => "Skipping to the next track".to_string(),
+                Err(e) => e,
+            },
+            None if radio => {
+                pick = true;
+                "Picking something else for the radio".to_string()
+            }
+            None => "Nothing else is queued".to_string(),
+        },
+    };
+    report(action).await;
+    if pick {
+        crate::radio::advance(ctx, guild_id, track).await;
+    }
+}
+
+/// The top search result for a failed track's title, unless that's the
+/// same track. Local files and live streams have nothing to search for.
+async fn alternative(ctx: &Context, entry: &QueueEntry) -> Option<QueueEntry> {
+    if entry.url.scheme() == "file" || entry.title == entry.url.as_str() {
+        return None;
+    }
+    let search = {
+        let typemap = ctx.data.read().await;
+        typemap.get::<UserData>().unwrap().search.clone()
+    };
+    let page = match search.search(&entry.title, 1, None).await {
+        Ok(page) => page,
+        Err(e) => {
+            warn!("no fallback for {}: {e}", entry.url);
+            return None;
+        }
+    };
+    let result = page.results.into_iter().next()?;
+    let url = Url::parse(&format!("https://youtube.com/watch?v={}", result.id)).ok()?;
+    let same = url.query() == entry.url.query() && entry.url.host_str() == url.host_str();
+    (!same).then_some(QueueEntry {
+        url,
+        title: result.title,
+        requested_in: entry.requested_in,
+        requested_by: entry.requested_by,
+    })
+}
+
+async fn report(ctx: &Context, channel_id: ChannelId, description: String) {
+    let embed = CreateEmbed::new()
+        .color(Colour::new(COLOR_ERROR))
+        .title("Track error")
+        .description(description)
+        .timestamp(Timestamp::now());
+    let message = CreateMessage::new().embed(embed);
+    if let Err(e) = channel_id.send_message(ctx, message).await {
+        warn!("could not report track error: {e}");
+    }
+}
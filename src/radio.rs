@@ -0,0 +1,214 @@
+use std::collections::HashSet;
+
+use log::{debug, warn};
+use rand::seq::IndexedRandom;
+use serenity::{
+    all::{ChannelId, Colour, CreateEmbed, CreateMessage, GuildId, Timestamp},
+    prelude::*,
+};
+use songbird::tracks::TrackHandle;
+use url::Url;
+
+use crate::{
+    COLOR_ERROR, UserData,
+    library::Library,
+    queue::{self, QueueEntry},
+    search::{SearchProvider, SearchResult},
+};
+
+/// Tracks played this recently aren't picked again.
+const NO_REPEAT: usize = 20;
+/// Related videos or seed results to pick among.
+const CANDIDATES: usize = 10;
+
+/// Autoplay for a guild: once the queue runs dry, [`advance`] picks
+/// something to follow what was last played.
+pub struct Radio {
+    /// What `/radio` was started with, searched when nothing else turns up.
+    pub seed: Option<String>,
+    /// Where picks are said to be asked for.
+    pub requested_in: ChannelId,
+}
+
+/// Plays the next pick after `current` ended, unless something else
+/// started or got queued meanwhile. Turns the radio off when nothing is
+/// left to pick.
+pub async fn advance(ctx: &Context, guild_id: GuildId, current: TrackHandle) {
+    let picked = pick(ctx, guild_id).await;
+
+    let mut typemap = ctx.data.write().await;
+    let data = typemap.get_mut::<UserData>().unwrap();
+    let Some(radio) = data.radios.get(&guild_id) else {
+        return;
+    };
+    if data.track_handles.get(&guild_id).map(|t| t.uuid()) != Some(current.uuid())
+        || data.queues.get(&guild_id).is_some_and(|q| !q.is_empty())
+    {
+        return;
+    }
+    let Some(entry) = picked else {
+        let channel_id = radio.requested_in;
+        data.radios.remove(&guild_id);
+        drop(typemap);
+        let embed = CreateEmbed::new()
+            .color(Colour::new(COLOR_ERROR))
+            .title("Radio")
+            .description("Ran out of things to play, so the radio is off")
+            .timestamp(Timestamp::now());
+        if let Err(e) = channel_id
+            .send_message(ctx, CreateMessage::new().embed(embed))
+            .await
+        {
+            warn!("could not report radio stopping: {e}");
+        }
+        return;
+    };
+    drop(typemap);
+    if let Err(e) = queue::play_after(ctx, guild_id, entry, &current).await {
+        warn!("could not play the radio's pick: {e}");
+    }
+}
+
+/// Something to follow the last played track, trying in turn: more by the
+/// same artist from the library, videos related to it, results for the
+/// seed, then a pick from the guild's history.
+pub async fn pick(ctx: &Context, guild_id: GuildId) -> Option<QueueEntry> {
+    let (seed, requested_in, recent, library, search, history) = {
+        let typemap = ctx.data.read().await;
+        let data = typemap.get::<UserData>().unwrap();
+        let radio = data.radios.get(&guild_id)?;
+        let recent: Vec<QueueEntry> = data
+            .recent
+            .get(&guild_id)
+            .map(|r| r.iter().cloned().collect())
+            .unwrap_or_default();
+        (
+            radio.seed.clone(),
+            radio.requested_in,
+            recent,
+            data.library.clone(),
+            data.search.clone(),
+            data.history.clone(),
+        )
+    };
+    let played: HashSet<String> = recent.iter().take(NO_REPEAT).map(|e| key(&e.url)).collect();
+    let fresh = |entries: Vec<QueueEntry>| -> Vec<QueueEntry> {
+        entries
+            .into_iter()
+            .filter(|e| !played.contains(&key(&e.url)))
+            .collect()
+    };
+
+    if let Some(last) = recent.first() {
+        if let Some(library) = &library {
+            let found = fresh(same_artist(library, last, requested_in));
+            if let Some(entry) = found.choose(&mut rand::rng()) {
+                debug!("radio: {} is by the same artist", entry.url);
+                return Some(entry.clone());
+            }
+        }
+        let found = fresh(related(&*search, last, requested_in).await);
+        if let Some(entry) = found.choose(&mut rand::rng()) {
+            debug!("radio: {} is related", entry.url);
+            return Some(entry.clone());
+        }
+    }
+    if let Some(seed) = &seed {
+        let found = fresh(seeded(&*search, seed, requested_in).await);
+        if let Some(entry) = found.choose(&mut rand::rng()) {
+            debug!("radio: {} is for {seed}", entry.url);
+            return Some(entry.clone());
+        }
+    }
+    // The guild's favourites are likelier.
+    let plays = tokio::task::spawn_blocking(move || history.plays(guild_id, None))
+        .await
+        .unwrap_or_default();
+    let favourites: Vec<(QueueEntry, usize)> = crate::history::by_track(&plays)
+        .into_iter()
+        .filter_map(|(play, count)| {
+            let entry = QueueEntry {
+                url: Url::parse(&play.url).ok()?,
+                title: play.title.clone(),
+                requested_in,
+                requested_by: None,
+            };
+            // Uploads may have been cleaned up since.
+            let gone = entry.url.to_file_path().is_ok_and(|p| !p.exists());
+            (!gone && !played.contains(&key(&entry.url))).then_some((entry, count))
+        })
+        .collect();
+    favourites
+        .choose_weighted(&mut rand::rng(), |(_, count)| *count)
+        .ok()
+        .map(|(entry, _)| entry.clone())
+}
+
+/// What tells tracks apart, so one video under two links counts once.
+fn key(url: &Url) -> String {
+    crate::youtube::video_id(url).unwrap_or_else(|| url.to_string())
+}
+
+fn same_artist(library: &Library, last: &QueueEntry, requested_in: ChannelId) -> Vec<QueueEntry> {
+    let Ok(path) = last.url.to_file_path() else {
+        return vec![];
+    };
+    let Some(artist) = library.track(&path).and_then(|t| t.artist) else {
+        return vec![];
+    };
+    library
+        .artist(&artist)
+        .into_iter()
+        .map(|t| QueueEntry {
+            url: t.url(),
+            title: t.display(),
+            requested_in,
+            requested_by: None,
+        })
+        .collect()
+}
+
+async fn related(
+    search: &dyn SearchProvider,
+    last: &QueueEntry,
+    requested_in: ChannelId,
+) -> Vec<QueueEntry> {
+    let Some(id) = crate::youtube::video_id(&last.url) else {
+        return vec![];
+    };
+    match search.related(&id, CANDIDATES).await {
+        Ok(results) => entries(results, requested_in),
+        Err(e) => {
+            warn!("no related videos for {id}: {e:?}");
+            vec![]
+        }
+    }
+}
+
+async fn seeded(
+    search: &dyn SearchProvider,
+    seed: &str,
+    requested_in: ChannelId,
+) -> Vec<QueueEntry> {
+    match search.search(seed, CANDIDATES, None).await {
+        Ok(page) => entries(page.results, requested_in),
+        Err(e) => {
+            warn!("radio search for {seed} failed: {e:?}");
+            vec![]
+        }
+    }
+}
+
+fn entries(results: Vec<SearchResult>, requested_in: ChannelId) -> Vec<QueueEntry> {
+    results
+        .into_iter()
+        .filter_map(|r| {
+            Some(QueueEntry {
+                url: Url::parse(&format!("https://youtube.com/watch?v={}", r.id)).ok()?,
+                title: r.title,
+                requested_in,
+                requested_by: None,
+            })
+        })
+        .collect()
+}
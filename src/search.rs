@@ -0,0 +1,364 @@
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use log::warn;
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+
+use crate::{cache::Cache, youtube::YoutubeClient};
+
+/// A video found by a [`SearchProvider`].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub id: String,
+    pub title: String,
+    pub channel: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+impl SearchResult {
+    /// Channel and duration, e.g. `Some Channel · 3:45`, whichever are known.
+    pub fn details(&self) -> String {
+        [self.channel.clone(), self.duration.map(clock)]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" · ")
+    }
+}
+
+/// `3:45`, or `1:03:45` past an hour.
+pub fn clock(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// One page of [`SearchProvider::search`] results. The tokens are opaque and
+/// only meaningful to the provider that handed them out.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
+
+/// Something that can look up YouTube videos.
+#[async_trait]
+pub trait SearchProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Up to `limit` results, starting at the page `token` refers to, or the
+    /// first page without one.
+    async fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        token: Option<&str>,
+    ) -> anyhow::Result<SearchPage>;
+
+    async fn video_title(&self, id: &str) -> anyhow::Result<String>;
+
+    /// A few results for autocomplete, which asks on every keystroke.
+    async fn suggest(&self, query: &str, limit: usize) -> anyhow::Result<Vec<SearchResult>> {
+        Ok(self.search(query, limit, None).await?.results)
+    }
+
+    /// Videos like `id`, for the radio.
+    async fn related(&self, id: &str, limit: usize) -> anyhow::Result<Vec<SearchResult>> {
+        let _ = (id, limit);
+        bail!("{} can't find related videos", self.name())
+    }
+}
+
+/// The YouTube Data API when `YOUTUBE_API_KEY` is set, falling back to yt-dlp
+/// whenever it fails (quota, network, ...). Without a key, only yt-dlp.
+pub fn from_env(http: HttpClient) -> Box<dyn SearchProvider> {
+    let dir = crate::cache::dir().join("youtube");
+    match YoutubeClient::from_env(http, dir.clone()) {
+        Some(client) => Box::new(Fallback {
+            primary: Box::new(YoutubeApi {
+                client,
+                cache: Cache::new(dir, VIDEO_TTL),
+            }),
+            fallback: Box::new(YtDlp),
+        }),
+        None => Box::new(YtDlp),
+    }
+}
+
+/// Titles and lengths hardly ever change, search rankings do.
+const VIDEO_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const SEARCH_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// The YouTube Data API. Responses are cached since every search costs a
+/// good share of the daily quota.
+pub struct YoutubeApi {
+    client: YoutubeClient,
+    cache: Cache,
+}
+
+impl YoutubeApi {
+    async fn search_uncached(
+        &self,
+        query: &str,
+        limit: usize,
+        token: Option<&str>,
+    ) -> anyhow::Result<SearchPage> {
+        let page = self.client.search(query, limit, token).await?;
+
+        let mut results: Vec<SearchResult> = page
+            .items
+            .into_iter()
+            .filter_map(|item| {
+                Some(SearchResult {
+                    id: item.id.videoid?,
+                    title: item.snippet.title,
+                    channel: item.snippet.channel_title,
+                    duration: None,
+                })
+            })
+            .take(limit)
+            .collect();
+
+        // Search results don't include the length, that takes a second call.
+        let mut missing = vec![];
+        for result in &mut results {
+            self.cache
+                .insert(&format!("title:{}", result.id), &result.title)
+                .await;
+            result.duration = self
+                .cache
+                .get(&format!("duration:{}", result.id), VIDEO_TTL)
+                .await;
+            if result.duration.is_none() {
+                missing.push(result.id.clone());
+            }
+        }
+        if !missing.is_empty() {
+            let ids: Vec<&str> = missing.iter().map(String::as_str).collect();
+            match self.client.video_durations(&ids).await {
+                Ok(durations) => {
+                    for result in &mut results {
+                        if let Some(duration) = durations.get(&result.id) {
+                            self.cache
+                                .insert(&format!("duration:{}", result.id), duration)
+                                .await;
+                            result.duration = Some(*duration);
+                        }
+                    }
+                }
+                Err(e) => warn!("could not look up video durations: {e:?}"),
+            }
+        }
+
+        Ok(SearchPage {
+            results,
+            next: page.next_page_token,
+            prev: page.prev_page_token,
+        })
+    }
+}
+
+#[async_trait]
+impl SearchProvider for YoutubeApi {
+    fn name(&self) -> &'static str {
+        "YouTube Data API"
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        token: Option<&str>,
+    ) -> anyhow::Result<SearchPage> {
+        let key = format!("search:{limit}:{}:{query}", token.unwrap_or(""));
+        if let Some(page) = self.cache.get(&key, SEARCH_TTL).await {
+            return Ok(page);
+        }
+        let page = self.search_uncached(query, limit, token).await?;
+        self.cache.insert(&key, &page).await;
+        Ok(page)
+    }
+
+    async fn video_title(&self, id: &str) -> anyhow::Result<String> {
+        let key = format!("title:{id}");
+        if let Some(title) = self.cache.get(&key, VIDEO_TTL).await {
+            return Ok(title);
+        }
+        let title = self.client.video_title(id).await?;
+        self.cache.insert(&key, &title).await;
+        Ok(title)
+    }
+}
+
+/// Searches through the `yt-dlp` binary with `ytsearchN:`, no API key needed.
+pub struct YtDlp;
+
+#[derive(Deserialize)]
+struct YtDlpEntry {
+    id: String,
+    title: String,
+    channel: Option<String>,
+    uploader: Option<String>,
+    duration: Option<f64>,
+}
+
+impl YtDlp {
+    async fn run(&self, args: &[&str]) -> anyhow::Result<Vec<YtDlpEntry>> {
+        let output = crate::ytdlp::config()
+            .command()
+            .args(args)
+            .output()
+            .await
+            .context("could not run yt-dlp")?;
+        if !output.status.success() {
+            bail!(
+                "yt-dlp failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        output
+            .stdout
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).context("unexpected yt-dlp output"))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl SearchProvider for YtDlp {
+    fn name(&self) -> &'static str {
+        "yt-dlp"
+    }
+
+    /// Tokens are result offsets; every page searches again for everything up
+    /// to its end, since `ytsearch` can't start partway through.
+    async fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        token: Option<&str>,
+    ) -> anyhow::Result<SearchPage> {
+        let offset: usize = token.and_then(|t| t.parse().ok()).unwrap_or(0);
+        let end = offset + limit;
+        let query = format!("ytsearch{end}:{query}");
+        let start = (offset + 1).to_string();
+        let entries = self
+            .run(&["-j", "--flat-playlist", "--playlist-start", &start, &query])
+            .await?;
+
+        let results: Vec<SearchResult> = entries
+            .into_iter()
+            .map(|e| SearchResult {
+                id: e.id,
+                title: e.title,
+                channel: e.channel.or(e.uploader),
+                duration: e.duration.map(Duration::from_secs_f64),
+            })
+            .take(limit)
+            .collect();
+
+        Ok(SearchPage {
+            next: (results.len() == limit).then(|| end.to_string()),
+            prev: (offset > 0).then(|| offset.saturating_sub(limit).to_string()),
+            results,
+        })
+    }
+
+    async fn video_title(&self, id: &str) -> anyhow::Result<String> {
+        let url = format!("https://www.youtube.com/watch?v={id}");
+        let entries = self
+            .run(&["-j", "--skip-download", "--no-playlist", &url])
+            .await?;
+        entries
+            .into_iter()
+            .next()
+            .map(|e| e.title)
+            .context("yt-dlp returned nothing")
+    }
+
+    /// From YouTube's mix for the video, which starts with the video itself.
+    async fn related(&self, id: &str, limit: usize) -> anyhow::Result<Vec<SearchResult>> {
+        let url = format!("https://www.youtube.com/watch?v={id}&list=RD{id}");
+        let end = (limit + 1).to_string();
+        let entries = self
+            .run(&["-j", "--flat-playlist", "--playlist-end", &end, &url])
+            .await?;
+        Ok(entries
+            .into_iter()
+            .filter(|e| e.id != id)
+            .map(|e| SearchResult {
+                id: e.id,
+                title: e.title,
+                channel: e.channel.or(e.uploader),
+                duration: e.duration.map(Duration::from_secs_f64),
+            })
+            .take(limit)
+            .collect())
+    }
+}
+
+pub struct Fallback {
+    primary: Box<dyn SearchProvider>,
+    fallback: Box<dyn SearchProvider>,
+}
+
+#[async_trait]
+impl SearchProvider for Fallback {
+    fn name(&self) -> &'static str {
+        self.primary.name()
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        token: Option<&str>,
+    ) -> anyhow::Result<SearchPage> {
+        match self.primary.search(query, limit, token).await {
+            Ok(page) => Ok(page),
+            Err(e) => {
+                warn!(
+                    "{} search failed, using {}: {e:?}",
+                    self.primary.name(),
+                    self.fallback.name()
+                );
+                // The primary's page token means nothing to the fallback.
+                self.fallback.search(query, limit, None).await
+            }
+        }
+    }
+
+    async fn video_title(&self, id: &str) -> anyhow::Result<String> {
+        match self.primary.video_title(id).await {
+            Ok(title) => Ok(title),
+            Err(e) => {
+                warn!(
+                    "{} title lookup failed, using {}: {e:?}",
+                    self.primary.name(),
+                    self.fallback.name()
+                );
+                self.fallback.video_title(id).await
+            }
+        }
+    }
+
+    /// Keystrokes aren't worth the primary's quota, so this goes straight to
+    /// the fallback.
+    async fn suggest(&self, query: &str, limit: usize) -> anyhow::Result<Vec<SearchResult>> {
+        self.fallback.suggest(query, limit).await
+    }
+
+    /// The YouTube Data API has no related videos anymore, so this goes
+    /// straight to the fallback.
+    async fn related(&self, id: &str, limit: usize) -> anyhow::Result<Vec<SearchResult>> {
+        self.fallback.related(id, limit).await
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use log::{debug, info, warn};
+use reqwest::{Client as HttpClient, StatusCode};
+use serde::Deserialize;
+use serenity::async_trait;
+use songbird::{
+    Event, EventContext, EventHandler,
+    tracks::{PlayMode, TrackHandle},
+};
+
+const DEFAULT_BASE_URL: &str = "https://sponsor.ajay.app/";
+/// Categories a guild can pick, as SponsorBlock names them.
+pub const CATEGORIES: &[(&str, &str)] = &[
+    ("sponsor", "Sponsors"),
+    ("selfpromo", "Self promotion"),
+    ("interaction", "Like and subscribe reminders"),
+    ("intro", "Intros"),
+    ("outro", "Outros and endcards"),
+    ("preview", "Previews and recaps"),
+    ("filler", "Filler tangents"),
+    ("music_offtopic", "Non-music parts of music videos"),
+];
+/// How often a playing track's position is checked against its segments.
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Segments ending this close to the position aren't worth a seek.
+const MIN_SKIP: Duration = Duration::from_secs(1);
+
+/// A part of a video to skip.
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub start: Duration,
+    pub end: Duration,
+    pub category: String,
+}
+
+/// Where segments come from, e.g. the SponsorBlock API.
+#[async_trait]
+pub trait SegmentProvider: Send + Sync {
+    /// Segments of the YouTube video `video_id` in any of `categories`.
+    async fn segments(&self, video_id: &str, categories: &[String])
+    -> anyhow::Result<Vec<Segment>>;
+}
+
+/// `SEGMENTS_FILE` for a JSON file of segments by video id, otherwise the
+/// SponsorBlock API at `SPONSORBLOCK_URL`.
+pub fn from_env(http: HttpClient) -> Arc<dyn SegmentProvider> {
+    if let Ok(path) = std::env::var("SEGMENTS_FILE") {
+        return Arc::new(SegmentFile { path: path.into() });
+    }
+    let mut base_url =
+        std::env::var("SPONSORBLOCK_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string());
+    if !base_url.ends_with('/') {
+        base_url.push('/');
+    }
+    Arc::new(SponsorBlock { http, base_url })
+}
+
+/// A segment as SponsorBlock returns it, `[start, end]` in seconds.
+#[derive(Deserialize)]
+struct ApiSegment {
+    segment: (f64, f64),
+    category: String,
+    #[serde(rename = "actionType", default = "skip")]
+    action_type: String,
+}
+
+fn skip() -> String {
+    "skip".to_string()
+}
+
+impl ApiSegment {
+    fn into_segment(self) -> Segment {
+        Segment {
+            start: Duration::from_secs_f64(self.segment.0.max(0.0)),
+            end: Duration::from_secs_f64(self.segment.1.max(0.0)),
+            category: self.category,
+        }
+    }
+}
+
+fn keep(found: Vec<ApiSegment>, categories: &[String]) -> Vec<Segment> {
+    found
+        .into_iter()
+        .filter(|s| s.action_type == "skip" && categories.contains(&s.category))
+        .map(ApiSegment::into_segment)
+        .filter(|s| s.end > s.start)
+        .collect()
+}
+
+pub struct SponsorBlock {
+    http: HttpClient,
+    base_url: String,
+}
+
+#[async_trait]
+impl SegmentProvider for SponsorBlock {
+    async fn segments(
+        &self,
+        video_id: &str,
+        categories: &[String],
+    ) -> anyhow::Result<Vec<Segment>> {
+        let response = self
+            .http
+            .get(format!("{}api/skipSegments", self.base_url))
+            .query(&[
+                ("videoID", video_id),
+                ("categories", &serde_json::to_string(categories)?),
+            ])
+            .send()
+            .await?;
+        // Videos nobody has submitted anything for.
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+        let found: Vec<ApiSegment> = response.error_for_status()?.json().await?;
+        Ok(keep(found, categories))
+    }
+}
+
+/// Segments from a local file shaped like `{"<video id>": [<API segment>]}`,
+/// read on every lookup so it can be edited while the bot runs.
+pub struct SegmentFile {
+    path: PathBuf,
+}
+
+#[async_trait]
+impl SegmentProvider for SegmentFile {
+    async fn segments(
+        &self,
+        video_id: &str,
+        categories: &[String],
+    ) -> anyhow::Result<Vec<Segment>> {
+        let bytes = tokio::fs::read(&self.path)
+            .await
+            .with_context(|| format!("could not read {}", self.path.display()))?;
+        let mut videos: HashMap<String, Vec<ApiSegment>> = serde_json::from_slice(&bytes)?;
+        Ok(keep(
+            videos.remove(video_id).unwrap_or_default(),
+            categories,
+        ))
+    }
+}
+
+/// Looks up the segments of `video_id` and, if there are any, skips them
+/// whenever `track` plays into one.
+pub async fn watch(
+    provider: Arc<dyn SegmentProvider>,
+    track: TrackHandle,
+    video_id: String,
+    categories: Vec<String>,
+) {
+    let segments = match provider.segments(&video_id, &categories).await {
+        Ok(segments) => segments,
+        Err(e) => {
+            warn!("no segments for {video_id}: {e}");
+            return;
+        }
+    };
+    debug!("{video_id} has {} segments to skip", segments.len());
+    if segments.is_empty() {
+        return;
+    }
+    let _ = track.add_event(
+        Event::Periodic(CHECK_INTERVAL, None),
+        SkipSegments {
+            segments,
+            sought: Mutex::default(),
+        },
+    );
+}
+
+struct SkipSegments {
+    segments: Vec<Segment>,
+    /// The segment last skipped, so a slow seek isn't asked for again while
+    /// the position hasn't moved yet.
+    sought: Mutex<Option<usize>>,
+}
+
+#[async_trait]
+impl EventHandler for SkipSegments {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
+        for (state, handle) in tracks.iter() {
+            if state.playing != PlayMode::Play {
+                continue;
+            }
+            let position = state.position;
+            let found = self
+                .segments
+                .iter()
+                .position(|s| s.start <= position && position + MIN_SKIP < s.end);
+            let mut sought = self.sought.lock().unwrap();
+            if found == *sought {
+                continue;
+            }
+            // Cleared once out of the segment, so it's skipped again if the
+            // track loops or is sought back into it.
+            *sought = found;
+            if let Some(segment) = found.map(|i| &self.segments[i]) {
+                info!(
+                    "Skipping {} segment {:?}..{:?}",
+                    segment.category, segment.start, segment.end
+                );
+                let _ = handle.seek(segment.end);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api(start: f64, end: f64, category: &str, action_type: &str) -> ApiSegment {
+        ApiSegment {
+            segment: (start, end),
+            category: category.to_string(),
+            action_type: action_type.to_string(),
+        }
+    }
+
+    fn categories(names: &[&str]) -> Vec<String> {
+        names.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn keeps_only_wanted_categories() {
+        let found = vec![
+            api(0.0, 10.0, "intro", "skip"),
+            api(20.0, 30.0, "sponsor", "skip"),
+            api(40.0, 50.0, "outro", "skip"),
+        ];
+        let kept = keep(found, &categories(&["sponsor", "outro"]));
+        let kept: Vec<&str> = kept.iter().map(|s| s.category.as_str()).collect();
+        assert_eq!(kept, ["sponsor", "outro"]);
+    }
+
+    #[test]
+    fn keeps_only_skips() {
+        let found = vec![
+            api(0.0, 10.0, "sponsor", "mute"),
+            api(20.0, 30.0, "sponsor", "poi"),
+            api(40.0, 50.0, "sponsor", "skip"),
+        ];
+        let kept = keep(found, &categories(&["sponsor"]));
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].start, Duration::from_secs(40));
+        assert_eq!(kept[0].end, Duration::from_secs(50));
+    }
+
+    #[test]
+    fn drops_empty_segments() {
+        let found = vec![
+            api(5.0, 5.0, "sponsor", "skip"),
+            api(9.0, 3.0, "sponsor", "skip"),
+            api(-2.0, 0.0, "sponsor", "skip"),
+            api(-2.0, 1.5, "sponsor", "skip"),
+        ];
+        let kept = keep(found, &categories(&["sponsor"]));
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].start, Duration::ZERO);
+        assert_eq!(kept[0].end, Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn action_type_defaults_to_skip() {
+        let found: Vec<ApiSegment> =
+            serde_json::from_str(r#"[{"segment": [1, 2], "category": "intro"}]"#).unwrap();
+        assert_eq!(keep(found, &categories(&["intro"])).len(), 1);
+    }
+
+    fn segment_file(name: &str, contents: &str) -> SegmentFile {
+        let path =
+            std::env::temp_dir().join(format!("segments-test-{}-{name}.json", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        SegmentFile { path }
+    }
+
+    #[tokio::test]
+    async fn file_segments_by_video() {
+        let file = segment_file(
+            "by-video",
+            r#"{
+                "abc": [
+                    {"segment": [0, 12.5], "category": "intro"},
+                    {"segment": [100, 130], "category": "sponsor", "actionType": "skip"},
+                    {"segment": [200, 210], "category": "sponsor", "actionType": "mute"}
+                ],
+                "other": [{"segment": [0, 5], "category": "sponsor"}]
+            }"#,
+        );
+
+        let found = file
+            .segments("abc", &categories(&["sponsor"]))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].start, Duration::from_secs(100));
+        assert_eq!(found[0].end, Duration::from_secs(130));
+
+        let found = file
+            .segments("abc", &categories(&["intro", "sponsor"]))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+
+        let found = file
+            .segments("missing", &categories(&["sponsor"]))
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+        std::fs::remove_file(&file.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_errors() {
+        let file = segment_file("invalid", "not json");
+        assert!(file.segments("abc", &[]).await.is_err());
+        std::fs::remove_file(&file.path).unwrap();
+
+        let file = SegmentFile {
+            path: std::env::temp_dir().join("segments-test-does-not-exist.json"),
+        };
+        assert!(file.segments("abc", &[]).await.is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serenity::all::GuildId;
+
+/// Where state that can't be rebuilt lives, `DATA_DIR` or `./data`.
+pub fn dir() -> PathBuf {
+    std::env::var("DATA_DIR")
+        .unwrap_or_else(|_| "data".to_string())
+        .into()
+}
+
+/// What each guild has chosen, saved to `settings.json` on every change.
+pub struct Settings {
+    path: PathBuf,
+    guilds: Mutex<HashMap<GuildId, GuildSettings>>,
+}
+
+/// Missing fields take their defaults, so older files keep loading.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// SponsorBlock categories to skip, none by default.
+    pub skip_categories: Vec<String>,
+    /// Text-to-speech voice, the engine's default when unset.
+    pub tts_voice: Option<String>,
+    /// Say "Now playing" before each track.
+    pub announce: bool,
+    /// Turn the music down while people talk, see [`crate::ducking`].
+    pub auto_duck: bool,
+    /// Voice louder than this, in dBFS, counts as talking.
+    pub duck_threshold: i32,
+    /// How much quieter the music gets, in percent.
+    pub duck_amount: u32,
+    pub duck_attack_ms: u64,
+    pub duck_release_ms: u64,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            skip_categories: vec![],
+            tts_voice: None,
+            announce: false,
+            auto_duck: false,
+            duck_threshold: -45,
+            duck_amount: 70,
+            duck_attack_ms: 100,
+            duck_release_ms: 800,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let path = dir().join("settings.json");
+        let guilds = std::fs::read(&path)
+            .ok()
+            .and_then(|b| serde_json::from_slice(&b).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            guilds: Mutex::new(guilds),
+        }
+    }
+
+    pub fn get(&self, guild_id: GuildId) -> GuildSettings {
+        self.guilds
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Changes a guild's settings and saves them all.
+    pub fn update(&self, guild_id: GuildId, f: impl FnOnce(&mut GuildSettings)) {
+        let mut guilds = self.guilds.lock().unwrap();
+        f(guilds.entry(guild_id).or_default());
+
+        let write = || -> std::io::Result<()> {
+            if let Some(dir) = self.path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(&self.path, serde_json::to_vec(&*guilds)?)
+        };
+        if let Err(e) = write() {
+            warn!("could not save settings: {e}");
+        }
+    }
+}
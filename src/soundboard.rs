@@ -0,0 +1,283 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail};
+use log::{info, warn};
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+use serenity::all::GuildId;
+use songbird::input::{File as FileInput, Input, cached::Decompressed};
+use symphonia::core::{
+    formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+    units::TimeBase,
+};
+
+/// Clips are meant to be short, they're kept decoded in memory.
+const MAX_CLIP_LENGTH: Duration = Duration::from_secs(15);
+const MAX_CLIP_BYTES: u64 = 5 * 1024 * 1024;
+const MAX_NAME_CHARS: usize = 32;
+const DEFAULT_VOLUME: u32 = 100;
+const DEFAULT_COOLDOWN: u64 = 5;
+
+/// Short clips per guild, stored under `sounds/` in the data directory and
+/// decoded the first time they're played.
+pub struct Soundboard {
+    dir: PathBuf,
+    clips: Mutex<HashMap<GuildId, BTreeMap<String, Clip>>>,
+    decoded: Mutex<HashMap<PathBuf, Decompressed>>,
+    last_played: Mutex<HashMap<(GuildId, String), Instant>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Clip {
+    /// File name within the guild's directory.
+    file: String,
+    /// Percent of the clip's own loudness.
+    pub volume: u32,
+    /// Seconds before the clip can be played again.
+    pub cooldown: u64,
+}
+
+impl Soundboard {
+    pub fn load() -> Self {
+        let dir = crate::settings::dir().join("sounds");
+        let clips = std::fs::read(dir.join("sounds.json"))
+            .ok()
+            .and_then(|b| serde_json::from_slice(&b).ok())
+            .unwrap_or_default();
+        Self {
+            dir,
+            clips: Mutex::new(clips),
+            decoded: Mutex::default(),
+            last_played: Mutex::default(),
+        }
+    }
+
+    /// Names of a guild's clips, sorted.
+    pub fn names(&self, guild_id: GuildId) -> Vec<String> {
+        let clips = self.clips.lock().unwrap();
+        clips
+            .get(&guild_id)
+            .map(|c| c.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, guild_id: GuildId, name: &str) -> Option<Clip> {
+        let clips = self.clips.lock().unwrap();
+        clips.get(&guild_id)?.get(name).cloned()
+    }
+
+    /// Downloads an uploaded clip and saves it as `name`, replacing any clip
+    /// of that name.
+    pub async fn add(
+        &self,
+        http: &HttpClient,
+        guild_id: GuildId,
+        name: &str,
+        url: &str,
+        filename: &str,
+        size: u64,
+    ) -> anyhow::Result<()> {
+        if name.is_empty()
+            || name.chars().count() > MAX_NAME_CHARS
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("names are up to {MAX_NAME_CHARS} letters, digits, - or _");
+        }
+        if size > MAX_CLIP_BYTES {
+            bail!("clips can be at most {} MB", MAX_CLIP_BYTES / 1024 / 1024);
+        }
+
+        let bytes = http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let extension = Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_default();
+        let file = match extension.as_str() {
+            "" => name.to_string(),
+            ext => format!("{name}.{ext}"),
+        };
+        let guild_dir = self.dir.join(guild_id.to_string());
+        tokio::fs::create_dir_all(&guild_dir).await?;
+        let path = guild_dir.join(&file);
+        let partial = guild_dir.join(format!(".{file}.part"));
+        tokio::fs::write(&partial, &bytes).await?;
+
+        let probe_path = partial.clone();
+        let length = tokio::task::spawn_blocking(move || length(&probe_path, &extension)).await?;
+        let length = match length {
+            Ok(length) => length,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return Err(e.context(format!("{filename} is not an audio file")));
+            }
+        };
+        if length > MAX_CLIP_LENGTH {
+            let _ = tokio::fs::remove_file(&partial).await;
+            bail!("clips can be at most {}s long", MAX_CLIP_LENGTH.as_secs());
+        }
+
+        let old = self.get(guild_id, name);
+        if let Some(old) = &old {
+            let old_path = guild_dir.join(&old.file);
+            self.decoded.lock().unwrap().remove(&old_path);
+            if old_path != path {
+                let _ = tokio::fs::remove_file(old_path).await;
+            }
+        }
+        tokio::fs::rename(&partial, &path).await?;
+        self.decoded.lock().unwrap().remove(&path);
+
+        self.update(guild_id, |clips| {
+            clips.insert(
+                name.to_string(),
+                Clip {
+                    file,
+                    volume: old.as_ref().map_or(DEFAULT_VOLUME, |c| c.volume),
+                    cooldown: old.as_ref().map_or(DEFAULT_COOLDOWN, |c| c.cooldown),
+                },
+            );
+        });
+        info!("Added sound {name} in {guild_id}");
+        Ok(())
+    }
+
+    pub async fn remove(&self, guild_id: GuildId, name: &str) -> bool {
+        let Some(clip) = self.get(guild_id, name) else {
+            return false;
+        };
+        let path = self.dir.join(guild_id.to_string()).join(&clip.file);
+        self.decoded.lock().unwrap().remove(&path);
+        let _ = tokio::fs::remove_file(&path).await;
+        self.update(guild_id, |clips| {
+            clips.remove(name);
+        });
+        true
+    }
+
+    /// Changes a clip's volume and cooldown, returning the result.
+    pub fn configure(
+        &self,
+        guild_id: GuildId,
+        name: &str,
+        volume: Option<u32>,
+        cooldown: Option<u64>,
+    ) -> Option<Clip> {
+        let mut changed = None;
+        self.update(guild_id, |clips| {
+            if let Some(clip) = clips.get_mut(name) {
+                clip.volume = volume.unwrap_or(clip.volume);
+                clip.cooldown = cooldown.unwrap_or(clip.cooldown);
+                changed = Some(clip.clone());
+            }
+        });
+        changed
+    }
+
+    /// An input for the clip, decoding it if it hasn't been yet. Fails while
+    /// it's cooling down.
+    pub async fn input(&self, guild_id: GuildId, name: &str) -> anyhow::Result<(Input, Clip)> {
+        let clip = self
+            .get(guild_id, name)
+            .with_context(|| format!("there's no sound called {name}"))?;
+
+        let key = (guild_id, name.to_string());
+        if let Some(at) = self.last_played.lock().unwrap().get(&key) {
+            let wait = Duration::from_secs(clip.cooldown).saturating_sub(at.elapsed());
+            if !wait.is_zero() {
+                bail!("{name} can be played again in {}s", wait.as_secs() + 1);
+            }
+        }
+
+        let path = self.dir.join(guild_id.to_string()).join(&clip.file);
+        let cached = self
+            .decoded
+            .lock()
+            .unwrap()
+            .get(&path)
+            .map(|d| d.new_handle());
+        let decoded = match cached {
+            Some(decoded) => decoded,
+            None => {
+                let decoded = Decompressed::new(FileInput::new(path.clone()).into())
+                    .await
+                    .with_context(|| format!("could not decode {name}"))?;
+                let mut loader = decoded.new_handle();
+                tokio::task::spawn_blocking(move || loader.raw.load_all()).await?;
+                self.decoded
+                    .lock()
+                    .unwrap()
+                    .insert(path, decoded.new_handle());
+                decoded
+            }
+        };
+        self.last_played.lock().unwrap().insert(key, Instant::now());
+        Ok((decoded.into(), clip))
+    }
+
+    fn update(&self, guild_id: GuildId, f: impl FnOnce(&mut BTreeMap<String, Clip>)) {
+        let mut clips = self.clips.lock().unwrap();
+        f(clips.entry(guild_id).or_default());
+
+        let write = || -> std::io::Result<()> {
+            std::fs::create_dir_all(&self.dir)?;
+            std::fs::write(self.dir.join("sounds.json"), serde_json::to_vec(&*clips)?)
+        };
+        if let Err(e) = write() {
+            warn!("could not save sounds: {e}");
+        }
+    }
+}
+
+/// How long the audio in the file is. When the container doesn't say, its
+/// packets are counted.
+fn length(path: &Path, extension: &str) -> anyhow::Result<Duration> {
+    let mut hint = Hint::new();
+    hint.with_extension(extension);
+    let mss = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let track = probed
+        .format
+        .default_track()
+        .filter(|t| t.codec_params.sample_rate.is_some())
+        .context("no audio track")?;
+    let (id, params) = (track.id, track.codec_params.clone());
+    let time_base = params
+        .time_base
+        .or_else(|| params.sample_rate.map(|rate| TimeBase::new(1, rate)))
+        .context("can't tell how long it is")?;
+    let frames = match params.n_frames {
+        Some(frames) => frames,
+        None => {
+            let mut frames = 0;
+            // Ends with an error at the end of the file, or past it.
+            while let Ok(packet) = probed.format.next_packet() {
+                if packet.track_id() == id {
+                    frames += packet.dur;
+                }
+            }
+            frames
+        }
+    };
+    let time = time_base.calc_time(frames);
+    Ok(Duration::from_secs_f64(time.seconds as f64 + time.frac))
+}
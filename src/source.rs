@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use log::{debug, warn};
+use reqwest::{
+    Client as HttpClient, Response,
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+};
+use songbird::input::{File, HttpRequest, Input};
+use url::Url;
+
+use crate::{
+    attachments::AttachmentCache,
+    library::Library,
+    live::{self, Live},
+};
+
+/// Extensions of files symphonia can play as they are.
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "mp3", "ogg", "oga", "opus", "flac", "wav", "m4a", "aac", "mp4", "webm", "weba", "mka", "mkv",
+];
+/// Sites whose pages always need yt-dlp, not worth probing.
+const EXTRACT_HOSTS: &[&str] = &["youtube.com", "youtu.be", "soundcloud.com", "bandcamp.com"];
+/// Discord serves attachments as plain files.
+const DIRECT_HOSTS: &[&str] = &["cdn.discordapp.com", "media.discordapp.net"];
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+/// Radio playlists are a few lines, anything bigger isn't one.
+const MAX_PLAYLIST_BYTES: usize = 64 * 1024;
+
+/// How a URL gets turned into audio.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Source {
+    /// A media file, streamed over HTTP with range requests for seeking.
+    Direct,
+    /// A web page yt-dlp has to dig the audio out of.
+    Extract,
+    /// An endless Icecast/SHOUTcast style stream.
+    Live,
+    /// An HLS playlist, live or not.
+    Hls,
+    /// A file on this machine, e.g. from the [`crate::library::Library`].
+    Local,
+}
+
+/// Something ready to hand to songbird.
+pub struct Playable {
+    pub input: Input,
+    /// Set for endless streams.
+    pub live: Option<Live>,
+    pub source: Source,
+}
+
+impl Source {
+    /// Guesses from the URL, then asks the server. `.m3u` and `.pls`
+    /// playlists are followed to the stream they list, so the URL to play
+    /// may differ from the one given. For a live stream the probe's
+    /// connection is handed back too, for [`Source::open`] to carry on with.
+    pub async fn detect(http: &HttpClient, url: &Url) -> (Self, Url, Option<Response>) {
+        let host_is = |hosts: &[&str]| {
+            url.host_str().is_some_and(|host| {
+                hosts
+                    .iter()
+                    .any(|h| host == *h || host.ends_with(&format!(".{h}")))
+            })
+        };
+        if url.scheme() == "file" {
+            return (Source::Local, url.clone(), None);
+        }
+        if host_is(EXTRACT_HOSTS) {
+            return (Source::Extract, url.clone(), None);
+        }
+        if host_is(DIRECT_HOSTS) {
+            return (Source::Direct, url.clone(), None);
+        }
+        let extension = url
+            .path_segments()
+            .and_then(|mut s| s.next_back())
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, ext)| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("m3u8") => return (Source::Hls, url.clone(), None),
+            Some("m3u") | Some("pls") => return follow_playlist(http, url).await,
+            _ => {}
+        }
+
+        // Streams don't answer HEAD reliably, so look at the headers of a
+        // GET and hang up before the body, unless it's a live stream. Only
+        // the headers are timed, the body may be endless.
+        let request = http.get(url.clone()).header("Icy-MetaData", "1").send();
+        let response = tokio::time::timeout(PROBE_TIMEOUT, request)
+            .await
+            .ok()
+            .and_then(Result::ok)
+            .filter(|r| r.status().is_success());
+        let Some(response) = response else {
+            let is_media = extension.is_some_and(|ext| MEDIA_EXTENSIONS.contains(&ext.as_str()));
+            let source = if is_media {
+                Source::Direct
+            } else {
+                Source::Extract
+            };
+            return (source, url.clone(), None);
+        };
+
+        let headers = response.headers();
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        debug!("{url} has content type {content_type:?}");
+        let is_icy = headers.keys().any(|k| k.as_str().starts_with("icy-"));
+        let has_length = headers.contains_key(CONTENT_LENGTH);
+
+        let source = match content_type.as_str() {
+            "application/vnd.apple.mpegurl" | "application/x-mpegurl" => Source::Hls,
+            "audio/x-mpegurl" | "audio/mpegurl" | "audio/x-scpls" => {
+                drop(response);
+                return follow_playlist(http, url).await;
+            }
+            _ if is_icy => Source::Live,
+            t if t.starts_with("audio/") || t.starts_with("video/") || t == "application/ogg" => {
+                if has_length {
+                    Source::Direct
+                } else {
+                    Source::Live
+                }
+            }
+            _ => Source::Extract,
+        };
+        match source {
+            Source::Live => (source, url.clone(), Some(response)),
+            _ => (source, url.clone(), None),
+        }
+    }
+
+    /// `first` is a connection to `url` already made by [`Source::detect`].
+    pub async fn open(self, http: HttpClient, url: &Url, first: Option<Response>) -> Playable {
+        match self {
+            Source::Direct => Playable {
+                input: HttpRequest::new(http, url.to_string()).into(),
+                live: None,
+                source: self,
+            },
+            Source::Live => {
+                let (input, live) = live::open(http, url.clone(), first).await;
+                Playable {
+                    input,
+                    live: Some(live),
+                    source: self,
+                }
+            }
+            Source::Hls => match live::open_hls(url) {
+                Ok((input, live)) => Playable {
+                    input,
+                    live: Some(live),
+                    source: self,
+                },
+                Err(e) => {
+                    warn!("could not run ffmpeg for {url}, trying yt-dlp: {e}");
+                    Playable {
+                        input: crate::ytdlp::config().input(http, url.to_string()).into(),
+                        live: None,
+                        source: self,
+                    }
+                }
+            },
+            Source::Extract => Playable {
+                input: crate::ytdlp::config().input(http, url.to_string()).into(),
+                live: None,
+                source: self,
+            },
+            Source::Local => Playable {
+                input: File::new(url.to_file_path().unwrap_or_default()).into(),
+                live: None,
+                source: self,
+            },
+        }
+    }
+}
+
+/// Finds the first stream in an `.m3u` or `.pls` playlist.
+async fn follow_playlist(http: &HttpClient, url: &Url) -> (Source, Url, Option<Response>) {
+    let body = match http.get(url.clone()).timeout(PROBE_TIMEOUT).send().await {
+        Ok(r) => read_capped(r, MAX_PLAYLIST_BYTES).await.unwrap_or_default(),
+        Err(_) => String::new(),
+    };
+
+    let entry = body
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| {
+            if line.starts_with('#') || line.starts_with('[') || line.is_empty() {
+                return None;
+            }
+            // PLS: `File1=http://...`, M3U: just the URL.
+            let target = match line.split_once('=') {
+                Some((key, value)) if key.to_ascii_lowercase().starts_with("file") => value,
+                Some(_) if !line.contains("://") => return None,
+                _ => line,
+            };
+            url.join(target.trim()).ok()
+        })
+        .next();
+
+    match entry {
+        Some(entry) if entry.path().to_ascii_lowercase().ends_with(".m3u8") => {
+            (Source::Hls, entry, None)
+        }
+        Some(entry) => (Source::Live, entry, None),
+        None => {
+            warn!("no stream found in playlist {url}");
+            (Source::Extract, url.clone(), None)
+        }
+    }
+}
+
+/// The body as text, or `None` once it runs past `max` bytes, whatever the
+/// server said its length was.
+async fn read_capped(response: Response, max: usize) -> Option<String> {
+    if response.content_length().is_some_and(|l| l as usize > max) {
+        return None;
+    }
+    let mut body = vec![];
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        body.extend_from_slice(&chunk.ok()?);
+        if body.len() > max {
+            return None;
+        }
+    }
+    Some(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Whether a link someone gave may be played: anything on the web, but
+/// files only from the library or the attachment cache.
+pub fn allowed(url: &Url, library: Option<&Library>, attachments: &AttachmentCache) -> bool {
+    match url.scheme() {
+        "http" | "https" => true,
+        "file" => url.to_file_path().is_ok_and(|path| {
+            library.is_some_and(|l| l.contains(&path)) || attachments.contains(&path)
+        }),
+        _ => false,
+    }
+}
+
+/// An input for `url`, going through yt-dlp only when it has to.
+pub async fn resolve(http: &HttpClient, url: &Url) -> Playable {
+    let (source, url, first) = Source::detect(http, url).await;
+    source.open(http.clone(), &url, first).await
+}
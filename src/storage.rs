@@ -0,0 +1,196 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{Context, bail};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::Client as HttpClient;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+/// SigV4 presigned URLs can't live longer than a week.
+const MAX_LINK_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// An S3-compatible bucket (AWS, MinIO, R2, ...) addressed path-style, so
+/// `S3_ENDPOINT` can be a plain `http://localhost:9000` for local testing.
+pub struct S3Storage {
+    http: HttpClient,
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    link_expiry: Duration,
+}
+
+impl S3Storage {
+    /// Returns `None` unless `S3_ENDPOINT`, `S3_BUCKET`, `S3_ACCESS_KEY` and
+    /// `S3_SECRET_KEY` are all set.
+    pub fn from_env(http: HttpClient) -> Option<Self> {
+        let endpoint = std::env::var("S3_ENDPOINT").ok()?;
+        let endpoint = match Url::parse(&endpoint) {
+            Ok(u) => u,
+            Err(e) => {
+                log::warn!("Ignoring invalid S3_ENDPOINT {endpoint:?}: {e}");
+                return None;
+            }
+        };
+        let link_expiry = std::env::var("S3_LINK_EXPIRY_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(MAX_LINK_EXPIRY)
+            .min(MAX_LINK_EXPIRY);
+
+        Some(Self {
+            http,
+            endpoint,
+            bucket: std::env::var("S3_BUCKET").ok()?,
+            region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".into()),
+            access_key: std::env::var("S3_ACCESS_KEY").ok()?,
+            secret_key: std::env::var("S3_SECRET_KEY").ok()?,
+            link_expiry,
+        })
+    }
+
+    /// Streams the file at `path` to `key` in the bucket.
+    pub async fn upload(&self, key: &str, path: &Path, content_type: &str) -> anyhow::Result<()> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .with_context(|| format!("opening {}", path.display()))?;
+        let len = file.metadata().await?.len();
+
+        let url = self.object_url(key);
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let host = host_header(&url);
+
+        let canonical_headers = format!(
+            "content-type:{content_type}\nhost:{host}\nx-amz-content-sha256:{UNSIGNED_PAYLOAD}\nx-amz-date:{amz_date}\n"
+        );
+        let signed_headers = "content-type;host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "PUT\n{}\n\n{canonical_headers}\n{signed_headers}\n{UNSIGNED_PAYLOAD}",
+            url.path()
+        );
+        let signature = self.sign(&date, &amz_date, &canonical_request);
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key,
+            self.scope(&date)
+        );
+
+        let response = self
+            .http
+            .put(url)
+            .header("content-type", content_type)
+            .header("content-length", len)
+            .header("x-amz-content-sha256", UNSIGNED_PAYLOAD)
+            .header("x-amz-date", &amz_date)
+            .header("authorization", authorization)
+            .body(file)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            bail!("upload failed with {status}: {}", response.text().await?);
+        }
+        Ok(())
+    }
+
+    /// A time-limited GET link for `key` that works without credentials.
+    pub fn presigned_url(&self, key: &str) -> Url {
+        let mut url = self.object_url(key);
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let host = host_header(&url);
+
+        let credential = format!("{}/{}", self.access_key, self.scope(&date));
+        let expires = self.link_expiry.as_secs().to_string();
+        let mut query = [
+            ("X-Amz-Algorithm", "AWS4-HMAC-SHA256"),
+            ("X-Amz-Credential", credential.as_str()),
+            ("X-Amz-Date", amz_date.as_str()),
+            ("X-Amz-Expires", expires.as_str()),
+            ("X-Amz-SignedHeaders", "host"),
+        ]
+        .map(|(k, v)| format!("{}={}", uri_encode(k, true), uri_encode(v, true)));
+        query.sort();
+        let query = query.join("&");
+
+        let canonical_request = format!(
+            "GET\n{}\n{query}\nhost:{host}\n\nhost\n{UNSIGNED_PAYLOAD}",
+            url.path()
+        );
+        let signature = self.sign(&date, &amz_date, &canonical_request);
+
+        url.set_query(Some(&format!("{query}&X-Amz-Signature={signature}")));
+        url
+    }
+
+    fn object_url(&self, key: &str) -> Url {
+        let mut url = self.endpoint.clone();
+        let base = url.path().trim_end_matches('/').to_string();
+        url.set_path(&format!(
+            "{base}/{}/{}",
+            uri_encode(&self.bucket, true),
+            uri_encode(key, false)
+        ));
+        url
+    }
+
+    fn scope(&self, date: &str) -> String {
+        format!("{date}/{}/s3/aws4_request", self.region)
+    }
+
+    fn sign(&self, date: &str, amz_date: &str, canonical_request: &str) -> String {
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{}\n{}",
+            self.scope(date),
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let key = hmac(
+            format!("AWS4{}", self.secret_key).as_bytes(),
+            date.as_bytes(),
+        );
+        let key = hmac(&key, self.region.as_bytes());
+        let key = hmac(&key, b"s3");
+        let key = hmac(&key, b"aws4_request");
+        hex::encode(hmac(&key, string_to_sign.as_bytes()))
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn host_header(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    }
+}
+
+/// RFC 3986 encoding as SigV4 expects it; `/` is kept in object keys.
+fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            b'/' if !encode_slash => out.push('/'),
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    path::PathBuf,
+    process::Stdio,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context as _, bail};
+use serenity::{all::UserId, async_trait, prelude::Mutex as AsyncMutex};
+use songbird::{
+    Call, Event, EventContext, EventHandler, TrackEvent,
+    input::{AudioStream, Input, LiveInput},
+    tracks::Track,
+};
+use symphonia::core::{io::MediaSource, probe::Hint};
+use tokio::io::AsyncWriteExt;
+
+use crate::ducking::Volume;
+
+const SPEECH_VOLUME: f32 = 1.0;
+
+/// Speech synthesis through a local engine, run once per message.
+pub struct Tts {
+    engine: Engine,
+    program: String,
+    /// Where piper's `<voice>.onnx` models are.
+    models: PathBuf,
+    default_voice: String,
+    pub max_chars: usize,
+    user_interval: Duration,
+    last_said: Mutex<HashMap<UserId, Instant>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Engine {
+    Espeak,
+    Piper,
+}
+
+impl Tts {
+    /// - `TTS_ENGINE`: `espeak-ng` (default) or `piper`
+    /// - `TTS_BINARY`: the engine's binary, if not on `PATH` under its name
+    /// - `TTS_VOICE`: the voice when a guild hasn't picked one, `en` for
+    ///   espeak-ng; for piper the name of a model in `PIPER_MODELS`
+    /// - `TTS_MAX_CHARS` (default 200) and `TTS_USER_SECS`, how long each
+    ///   user waits between messages (default 10)
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let engine = match var("TTS_ENGINE").as_deref() {
+            Some("piper") => Engine::Piper,
+            _ => Engine::Espeak,
+        };
+        let name = match engine {
+            Engine::Espeak => "espeak-ng",
+            Engine::Piper => "piper",
+        };
+        Self {
+            engine,
+            program: var("TTS_BINARY").unwrap_or_else(|| name.to_string()),
+            models: var("PIPER_MODELS").unwrap_or_else(|| ".".into()).into(),
+            default_voice: var("TTS_VOICE").unwrap_or_else(|| "en".to_string()),
+            max_chars: var("TTS_MAX_CHARS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(200),
+            user_interval: Duration::from_secs(
+                var("TTS_USER_SECS")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(10),
+            ),
+            last_said: Mutex::default(),
+        }
+    }
+
+    /// Whether `user` may have something said now, recording it if so.
+    /// Otherwise how long until they may.
+    pub fn allow(&self, user: UserId) -> Result<(), Duration> {
+        let mut last = self.last_said.lock().unwrap();
+        last.retain(|_, at| at.elapsed() < self.user_interval);
+        if let Some(at) = last.get(&user) {
+            return Err(self.user_interval - at.elapsed());
+        }
+        last.insert(user, Instant::now());
+        Ok(())
+    }
+
+    /// Speaks `text` with `voice`, or the default voice, as a WAV input.
+    pub async fn synthesize(&self, text: &str, voice: Option<&str>) -> anyhow::Result<Input> {
+        if text.chars().count() > self.max_chars {
+            bail!("that's longer than {} characters", self.max_chars);
+        }
+        let voice = voice.unwrap_or(&self.default_voice);
+        let mut command = tokio::process::Command::new(&self.program);
+        match self.engine {
+            // Text comes on stdin, so it's never taken for an option.
+            Engine::Espeak => command.args(["-v", voice, "--stdout"]),
+            Engine::Piper => {
+                let model = self.models.join(format!("{voice}.onnx"));
+                command
+                    .arg("--model")
+                    .arg(model)
+                    .args(["--output_file", "-"])
+            }
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("could not run {}", self.program))?;
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(text.as_bytes()).await?;
+        drop(stdin);
+
+        let output = child.wait_with_output().await?;
+        if !output.status.success() || output.stdout.is_empty() {
+            bail!(
+                "{} failed: {}",
+                self.program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let mut hint = Hint::new();
+        hint.with_extension("wav");
+        Ok(Input::Live(
+            LiveInput::Raw(AudioStream {
+                input: Box::new(Cursor::new(output.stdout)) as Box<dyn MediaSource>,
+                hint: Some(hint),
+            }),
+            None,
+        ))
+    }
+}
+
+/// Voices end up in arguments and file names, so only plain names like
+/// `en-us`, `en+f3` or `en_GB-alba-medium` are taken.
+pub fn is_valid_voice(voice: &str) -> bool {
+    !voice.is_empty()
+        && !voice.starts_with('-')
+        && voice
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '+'))
+}
+
+/// Plays `speech` over the music, which is turned down until it's over.
+pub async fn speak(call: Arc<AsyncMutex<Call>>, volume: Arc<Volume>, speech: Input) {
+    let speech = call
+        .lock()
+        .await
+        .play(Track::from(speech).volume(SPEECH_VOLUME));
+    volume.speech_started();
+    // Both fire when playback fails, only the first one should count.
+    let done = Arc::new(AtomicBool::new(false));
+    for event in [TrackEvent::End, TrackEvent::Error] {
+        let unduck = Unduck {
+            volume: volume.clone(),
+            done: done.clone(),
+        };
+        if speech.add_event(event.into(), unduck).is_err() {
+            // Already over.
+            if !done.swap(true, Ordering::Relaxed) {
+                volume.speech_ended();
+            }
+        }
+    }
+}
+
+/// Gives the music its volume back once the speech is done.
+struct Unduck {
+    volume: Arc<Volume>,
+    done: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl EventHandler for Unduck {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        if !self.done.swap(true, Ordering::Relaxed) {
+            self.volume.speech_ended();
+        }
+        None
+    }
+}
@@ -1,43 +1,349 @@
-use serde::Deserialize;
+use std::{collections::HashMap, fmt, path::PathBuf, sync::Mutex, time::Duration};
 
-pub async fn get_video_title(id: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let api_key = std::env::var("YOUTUBE_API_KEY")?;
-    let url = format!(
-        "https://www.googleapis.com/youtube/v3/videos?key={}&id={}&part=snippet&hl=en",
-        api_key, id
-    );
+use chrono::{FixedOffset, NaiveDate, Utc};
+use log::warn;
+use reqwest::{Client as HttpClient, StatusCode};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use url::Url;
 
-    let response = client.get(&url).send().await?; // Send the HTTP GET request
+const DEFAULT_BASE_URL: &str = "https://www.googleapis.com/youtube/v3/";
 
-    // Check if the response was successful
-    if !response.status().is_success() {
-        println!("API request failed with status: {}", response.status());
-        println!("Response body: {}", response.text().await?);
-        return Err("API request failed".into());
+#[derive(Debug)]
+pub enum Error {
+    /// Our own count says today's quota is spent, nothing was sent.
+    QuotaUsedUp {
+        used: u32,
+        limit: u32,
+    },
+    /// The API refused the call for lack of quota.
+    QuotaExceeded,
+    Http(reqwest::Error),
+    Status {
+        status: StatusCode,
+        body: String,
+    },
+    /// An error object in an otherwise successful response.
+    Api(String),
+    NotFound(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::QuotaUsedUp { used, limit } => {
+                write!(f, "YouTube API quota used up ({used}/{limit})")
+            }
+            Error::QuotaExceeded => write!(f, "YouTube API quota exceeded"),
+            Error::Http(e) => write!(f, "YouTube API request failed: {e}"),
+            Error::Status { status, .. } => write!(f, "YouTube API returned {status}"),
+            Error::Api(message) => write!(f, "YouTube API error: {message}"),
+            Error::NotFound(id) => write!(f, "no video with id {id}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+/// The video id of a `youtube.com/watch?v=`, `youtu.be/` or `/shorts/`
+/// link.
+pub fn video_id(url: &Url) -> Option<String> {
+    let host = url
+        .host_str()?
+        .trim_start_matches("www.")
+        .trim_start_matches("m.");
+    let mut segments = url.path_segments()?;
+    let id = match host {
+        "youtu.be" => segments.next().map(str::to_string),
+        "youtube.com" | "music.youtube.com" => match segments.next() {
+            Some("watch") => url
+                .query_pairs()
+                .find(|(k, _)| k == "v")
+                .map(|(_, v)| v.into_owned()),
+            Some("shorts" | "live") => segments.next().map(str::to_string),
+            _ => None,
+        },
+        _ => None,
+    };
+    id.filter(|id| !id.is_empty())
+}
+
+/// Quota units charged per call, see
+/// <https://developers.google.com/youtube/v3/determine_quota_cost>.
+pub const SEARCH_COST: u32 = 100;
+pub const VIDEOS_COST: u32 = 1;
+
+/// Keeps count of the API units spent today, so that calls stop before Google
+/// starts refusing them. Persisted so restarts don't reset the count.
+pub struct Quota {
+    limit: u32,
+    /// Warn once usage passes this many units.
+    warn_at: u32,
+    path: PathBuf,
+    state: Mutex<QuotaState>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct QuotaState {
+    day: Option<NaiveDate>,
+    used: u32,
+}
+
+impl Quota {
+    /// `YOUTUBE_QUOTA` is the daily limit, 10000 by default, and
+    /// `YOUTUBE_QUOTA_WARN` the percentage of it to warn at, default 80.
+    pub fn from_env(dir: PathBuf) -> Self {
+        let limit = std::env::var("YOUTUBE_QUOTA")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10_000);
+        let warn_percent: u32 = std::env::var("YOUTUBE_QUOTA_WARN")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(80);
+        Self::new(limit, warn_percent, dir)
+    }
+
+    /// Picks up today's count from `dir` if there is one.
+    pub fn new(limit: u32, warn_percent: u32, dir: PathBuf) -> Self {
+        let path = dir.join("quota.json");
+        let state = std::fs::read(&path)
+            .ok()
+            .and_then(|b| serde_json::from_slice(&b).ok())
+            .unwrap_or_default();
+
+        Self {
+            limit,
+            warn_at: (limit as u64 * warn_percent.min(100) as u64 / 100).max(1) as u32,
+            path,
+            state: Mutex::new(state),
+        }
     }
 
-    let res: YoutubeVideo = response.json().await?; // Parse the response body as JSON
+    /// Books `cost` units, or fails without booking if that would go over
+    /// today's limit.
+    fn spend(&self, cost: u32) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let today = quota_day();
+        if state.day != Some(today) {
+            *state = QuotaState {
+                day: Some(today),
+                used: 0,
+            };
+        }
+
+        if state.used + cost > self.limit {
+            return Err(Error::QuotaUsedUp {
+                used: state.used,
+                limit: self.limit,
+            });
+        }
+        let before = state.used;
+        state.used += cost;
+        if before < self.warn_at && state.used >= self.warn_at {
+            warn!(
+                "YouTube API quota almost used up: {}/{} units today",
+                state.used, self.limit
+            );
+        }
+        self.save(&state);
+        Ok(())
+    }
+
+    /// Google says we're out, whatever our count says.
+    fn exhaust(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.day = Some(quota_day());
+        state.used = self.limit;
+        self.save(&state);
+    }
 
-    // Check for API errors
-    if let Some(error) = res.error {
-        print!("API returned an error: {:?}", error.message);
-        return Err("API returned an error".into());
+    fn save(&self, state: &QuotaState) {
+        let write = || -> std::io::Result<()> {
+            if let Some(dir) = self.path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(&self.path, serde_json::to_vec(state)?)
+        };
+        if let Err(e) = write() {
+            warn!("could not save YouTube quota: {e}");
+        }
     }
+}
+
+/// Quota resets at midnight Pacific time. Daylight saving is ignored, so the
+/// count may reset an hour late for half the year.
+fn quota_day() -> NaiveDate {
+    let pacific = FixedOffset::west_opt(8 * 3600).unwrap();
+    Utc::now().with_timezone(&pacific).date_naive()
+}
 
-    // Extract video items and add to the videos vector
-    Ok(res.items.first().unwrap().snippet.title.clone())
+/// Talks to the YouTube Data API, or anything serving the same endpoints
+/// (a proxy, a mock) at `YOUTUBE_API_URL`.
+pub struct YoutubeClient {
+    http: HttpClient,
+    base_url: Url,
+    api_key: String,
+    quota: Quota,
+}
+
+impl YoutubeClient {
+    pub fn new(http: HttpClient, mut base_url: Url, api_key: String, quota: Quota) -> Self {
+        // Without the slash, joining would replace the last path segment.
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        Self {
+            http,
+            base_url,
+            api_key,
+            quota,
+        }
+    }
+
+    /// `None` without `YOUTUBE_API_KEY`. Quota is kept in `dir`.
+    pub fn from_env(http: HttpClient, dir: PathBuf) -> Option<Self> {
+        let api_key = std::env::var("YOUTUBE_API_KEY").ok()?;
+        let base = std::env::var("YOUTUBE_API_URL").unwrap_or(DEFAULT_BASE_URL.to_string());
+        let base_url = match Url::parse(&base) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("invalid YOUTUBE_API_URL {base:?}, using the default: {e}");
+                Url::parse(DEFAULT_BASE_URL).unwrap()
+            }
+        };
+        Some(Self::new(http, base_url, api_key, Quota::from_env(dir)))
+    }
+
+    /// Books the call against the quota, then fetches and parses
+    /// `<base>/<endpoint>?<query>`.
+    async fn get<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        cost: u32,
+        query: &[(&str, &str)],
+    ) -> Result<T, Error> {
+        self.quota.spend(cost)?;
+        let url = self
+            .base_url
+            .join(endpoint)
+            .expect("endpoint is a valid relative URL");
+        let response = self
+            .http
+            .get(url)
+            .query(&[("key", self.api_key.as_str())])
+            .query(query)
+            .send()
+            .await?;
+
+        // Check if the response was successful
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await?;
+            if body.contains("quotaExceeded") {
+                self.quota.exhaust();
+                return Err(Error::QuotaExceeded);
+            }
+            return Err(Error::Status { status, body });
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn video_title(&self, id: &str) -> Result<String, Error> {
+        let res: YoutubeVideo = self
+            .get(
+                "videos",
+                VIDEOS_COST,
+                &[("id", id), ("part", "snippet"), ("hl", "en")],
+            )
+            .await?;
+
+        // Check for API errors
+        if let Some(error) = res.error {
+            return Err(Error::Api(error.message));
+        }
+
+        res.items
+            .into_iter()
+            .find_map(|v| v.snippet)
+            .map(|s| s.title)
+            .ok_or_else(|| Error::NotFound(id.to_string()))
+    }
+
+    pub async fn search(
+        &self,
+        query: &str,
+        max_results: usize,
+        page_token: Option<&str>,
+    ) -> Result<YoutubeSearch, Error> {
+        let max_results = max_results.to_string();
+        let mut params = vec![
+            ("q", query),
+            ("part", "id,snippet"),
+            ("hl", "en"),
+            ("type", "video"),
+            ("maxResults", &max_results),
+        ];
+        if let Some(token) = page_token {
+            params.push(("pageToken", token));
+        }
+
+        let res: YoutubeSearch = self.get("search", SEARCH_COST, &params).await?;
+
+        // Check for API errors
+        if let Some(error) = res.error {
+            return Err(Error::Api(error.message));
+        }
+        Ok(res)
+    }
+
+    /// Looks up the length of each video, keyed by id.
+    pub async fn video_durations(&self, ids: &[&str]) -> Result<HashMap<String, Duration>, Error> {
+        let ids = ids.join(",");
+        let res: YoutubeVideo = self
+            .get(
+                "videos",
+                VIDEOS_COST,
+                &[("id", &ids), ("part", "contentDetails")],
+            )
+            .await?;
+        if let Some(error) = res.error {
+            return Err(Error::Api(error.message));
+        }
+
+        Ok(res
+            .items
+            .into_iter()
+            .filter_map(|v| Some((v.id, parse_duration(&v.content_details?.duration)?)))
+            .collect())
+    }
 }
 
 #[derive(Deserialize)]
-struct YoutubeError {
+struct ApiError {
     pub message: String,
 }
 
 #[derive(Deserialize)]
-struct YoutubeSearch {
+#[serde(rename_all = "camelCase")]
+pub struct YoutubeSearch {
     pub items: Vec<YoutubeSearchItem>,
-    pub error: Option<YoutubeError>,
+    pub next_page_token: Option<String>,
+    pub prev_page_token: Option<String>,
+    error: Option<ApiError>,
 }
 
 #[derive(Deserialize)]
@@ -56,47 +362,203 @@ pub struct YoutubeSearchID {
 #[derive(Deserialize)]
 struct YoutubeVideo {
     pub items: Vec<YoutubeVideoItem>,
-    pub error: Option<YoutubeError>,
+    pub error: Option<ApiError>,
 }
 
 #[derive(Deserialize)]
 pub struct YoutubeVideoItem {
     pub id: String,
-    pub snippet: YoutubeSnippet,
+    pub snippet: Option<YoutubeSnippet>,
+    #[serde(rename = "contentDetails")]
+    pub content_details: Option<YoutubeContentDetails>,
 }
 
 #[derive(Deserialize)]
 pub struct YoutubeSnippet {
     pub title: String,
+    #[serde(rename = "channelTitle")]
+    pub channel_title: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct YoutubeContentDetails {
+    /// ISO 8601, e.g. `PT1H2M3S`.
+    pub duration: String,
+}
+
+/// Parses the `PT#H#M#S` durations the API returns. Days are allowed for
+/// very long streams, anything else gives `None`.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.strip_prefix('P')?;
+    let mut secs = 0;
+    let mut num = String::new();
+    for c in s.chars() {
+        let unit = match c {
+            'T' => continue,
+            '0'..='9' => {
+                num.push(c);
+                continue;
+            }
+            'D' => 86400,
+            'H' => 3600,
+            'M' => 60,
+            'S' => 1,
+            _ => return None,
+        };
+        secs += num.parse::<u64>().ok()? * unit;
+        num.clear();
+    }
+    Some(Duration::from_secs(secs))
 }
 
-pub async fn search_videos(
-    query: &str,
-) -> Result<Vec<YoutubeSearchItem>, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let api_key = std::env::var("YOUTUBE_API_KEY")?;
-    let url = format!(
-        "https://www.googleapis.com/youtube/v3/search?key={}&q={}&part=id,snippet&hl=en&type=video",
-        api_key, query
-    );
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
 
-    let response = client.get(&url).send().await?; // Send the HTTP GET request
+    use super::*;
 
-    // Check if the response was successful
-    if !response.status().is_success() {
-        println!("API request failed with status: {}", response.status());
-        println!("Response body: {}", response.text().await?);
-        return Err("API request failed".into());
+    /// Answers one request with `status` and `body`, handing back the request
+    /// line it got.
+    async fn serve(status: &'static str, body: &'static str) -> (Url, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/youtube/v3", listener.local_addr().unwrap());
+        let request = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut head = vec![];
+            let mut buf = [0; 1024];
+            while !head.ends_with(b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                assert!(n > 0, "connection closed mid-request");
+                head.extend_from_slice(&buf[..n]);
+            }
+            let response = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            let head = String::from_utf8(head).unwrap();
+            head.lines().next().unwrap().to_string()
+        });
+        (Url::parse(&base).unwrap(), request)
     }
-    // let t = response.text().await?;
-    // info!("{}", t);
-    let res: YoutubeSearch = response.json().await?; // serde_json::from_str(&t)?;
 
-    // Check for API errors
-    if let Some(error) = res.error {
-        print!("API returned an error: {:?}", error.message);
-        return Err("API returned an error".into());
+    fn client(base_url: Url, limit: u32, name: &str) -> YoutubeClient {
+        let dir = std::env::temp_dir().join(format!("youtube-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        YoutubeClient::new(
+            HttpClient::new(),
+            base_url,
+            "key".to_string(),
+            Quota::new(limit, 80, dir),
+        )
     }
 
-    Ok(res.items)
+    #[tokio::test]
+    async fn search_encodes_query() {
+        let (base, request) = serve("200 OK", r#"{"items":[]}"#).await;
+        let client = client(base, 10_000, "encode");
+
+        client.search("a&b#c", 5, None).await.unwrap();
+
+        let request = request.await.unwrap();
+        assert!(request.starts_with("GET /youtube/v3/search?"), "{request}");
+        assert!(request.contains("q=a%26b%23c&"), "{request}");
+    }
+
+    #[tokio::test]
+    async fn quota_exceeded_is_remembered() {
+        let body = r#"{"error":{"errors":[{"reason":"quotaExceeded"}]}}"#;
+        let (base, _request) = serve("403 Forbidden", body).await;
+        let client = client(base, 10_000, "exceeded");
+
+        let err = client.video_title("id").await.unwrap_err();
+        assert!(matches!(err, Error::QuotaExceeded), "{err:?}");
+        // Nothing is listening anymore, so this must not reach the server.
+        let err = client.video_title("id").await.unwrap_err();
+        assert!(
+            matches!(err, Error::QuotaUsedUp { limit: 10_000, .. }),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn quota_used_up_sends_nothing() {
+        let (base, request) = serve("200 OK", r#"{"items":[]}"#).await;
+        let client = client(base, SEARCH_COST - 1, "used-up");
+
+        let Err(err) = client.search("q", 5, None).await else {
+            panic!("searched over the limit");
+        };
+        assert!(matches!(err, Error::QuotaUsedUp { used: 0, .. }), "{err:?}");
+        assert!(!request.is_finished());
+        request.abort();
+    }
+
+    #[tokio::test]
+    async fn http_errors_keep_status_and_body() {
+        let (base, _request) = serve("500 Internal Server Error", "oops").await;
+        let client = client(base, 10_000, "status");
+
+        let err = client.video_title("id").await.unwrap_err();
+        match err {
+            Error::Status { status, body } => {
+                assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+                assert_eq!(body, "oops");
+            }
+            err => panic!("{err:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn api_errors_in_successful_responses() {
+        let body = r#"{"items":[],"error":{"message":"bad request"}}"#;
+        let (base, _request) = serve("200 OK", body).await;
+        let client = client(base, 10_000, "api");
+
+        let err = client.video_title("id").await.unwrap_err();
+        assert!(
+            matches!(&err, Error::Api(m) if m == "bad request"),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_videos_are_not_found() {
+        let (base, _request) = serve("200 OK", r#"{"items":[]}"#).await;
+        let client = client(base, 10_000, "not-found");
+
+        let err = client.video_title("gone").await.unwrap_err();
+        assert!(
+            matches!(&err, Error::NotFound(id) if id == "gone"),
+            "{err:?}"
+        );
+    }
+
+    fn id(url: &str) -> Option<String> {
+        video_id(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn video_ids() {
+        let want = Some("dQw4w9WgXcQ".to_string());
+        assert_eq!(id("https://www.youtube.com/watch?v=dQw4w9WgXcQ"), want);
+        assert_eq!(id("https://m.youtube.com/watch?list=x&v=dQw4w9WgXcQ"), want);
+        assert_eq!(id("https://music.youtube.com/watch?v=dQw4w9WgXcQ"), want);
+        assert_eq!(id("https://youtu.be/dQw4w9WgXcQ?t=42"), want);
+        assert_eq!(id("https://youtube.com/shorts/dQw4w9WgXcQ"), want);
+        assert_eq!(id("https://www.youtube.com/live/dQw4w9WgXcQ?si=abc"), want);
+    }
+
+    #[test]
+    fn not_video_ids() {
+        assert_eq!(id("https://youtube.com/watch"), None);
+        assert_eq!(id("https://youtube.com/watch?v="), None);
+        assert_eq!(id("https://youtu.be/"), None);
+        assert_eq!(id("https://youtube.com/@channel"), None);
+        assert_eq!(id("https://example.com/watch?v=dQw4w9WgXcQ"), None);
+    }
 }
@@ -0,0 +1,172 @@
+use std::{sync::OnceLock, time::Duration};
+
+use anyhow::{Context as _, bail};
+
+use chrono::{NaiveDate, Utc};
+use log::{info, warn};
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use songbird::input::YoutubeDl;
+use tokio::process::Command;
+
+/// yt-dlp releases are frequent and sites break old ones quickly.
+const OUTDATED_DAYS: i64 = 90;
+
+static CONFIG: OnceLock<YtDlpConfig> = OnceLock::new();
+
+/// How `yt-dlp` gets run, for playback and for searching.
+pub struct YtDlpConfig {
+    program: String,
+    args: Vec<String>,
+}
+
+/// What yt-dlp knows about a video beyond its stream.
+#[derive(Deserialize, Default)]
+pub struct Info {
+    #[serde(default, deserialize_with = "seconds")]
+    pub duration: Option<Duration>,
+    #[serde(default, deserialize_with = "chapters")]
+    pub chapters: Vec<Chapter>,
+}
+
+/// A section of a long video, e.g. one song of a mix.
+#[derive(Deserialize, Clone)]
+pub struct Chapter {
+    #[serde(rename = "start_time", deserialize_with = "required_seconds")]
+    pub start: Duration,
+    pub title: String,
+}
+
+fn seconds<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+    let secs: Option<f64> = Option::deserialize(d)?;
+    Ok(secs.filter(|s| *s >= 0.0).map(Duration::from_secs_f64))
+}
+
+fn required_seconds<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+    Ok(seconds(d)?.unwrap_or_default())
+}
+
+/// `null` when a video has none.
+fn chapters<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Vec<Chapter>, D::Error> {
+    Ok(Option::deserialize(d)?.unwrap_or_default())
+}
+
+/// The chapter playing at `position`.
+pub fn current_chapter(chapters: &[Chapter], position: Duration) -> Option<usize> {
+    chapters.iter().rposition(|c| c.start <= position)
+}
+
+/// Read from the environment the first time it's needed.
+pub fn config() -> &'static YtDlpConfig {
+    CONFIG.get_or_init(YtDlpConfig::from_env)
+}
+
+impl YtDlpConfig {
+    /// - `YTDLP_PATH`: the binary, `yt-dlp` from `PATH` by default
+    /// - `YTDLP_COOKIES`: a Netscape cookies file, for age-restricted videos
+    /// - `YTDLP_FORMAT`: preferred audio codec, e.g. `opus` or `aac`
+    /// - `YTDLP_BITRATE`: preferred bitrate in kbps, the closest one wins
+    /// - `YTDLP_USER_AGENT` and `YTDLP_PROXY`
+    /// - `YTDLP_ARGS`: anything else, split on whitespace
+    fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let mut args = vec![];
+        if let Some(cookies) = var("YTDLP_COOKIES") {
+            args.extend(["--cookies".to_string(), cookies]);
+        }
+        // Songbird passes its own `-f`, which would override ours, so
+        // preferences go through sorting instead.
+        let sort: Vec<String> = [
+            var("YTDLP_FORMAT").map(|f| format!("acodec:{f}")),
+            var("YTDLP_BITRATE").map(|b| format!("abr~{b}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if !sort.is_empty() {
+            args.extend(["--format-sort".to_string(), sort.join(",")]);
+        }
+        if let Some(user_agent) = var("YTDLP_USER_AGENT") {
+            args.extend(["--user-agent".to_string(), user_agent]);
+        }
+        if let Some(proxy) = var("YTDLP_PROXY") {
+            args.extend(["--proxy".to_string(), proxy]);
+        }
+        if let Some(extra) = var("YTDLP_ARGS") {
+            args.extend(extra.split_whitespace().map(str::to_string));
+        }
+
+        Self {
+            program: var("YTDLP_PATH").unwrap_or_else(|| "yt-dlp".to_string()),
+            args,
+        }
+    }
+
+    /// A songbird input extracting `url`.
+    pub fn input(&'static self, http: HttpClient, url: String) -> YoutubeDl<'static> {
+        YoutubeDl::new_ytdl_like(&self.program, http, url).user_args(self.args.clone())
+    }
+
+    /// A command with the configured options, for further arguments.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command
+    }
+
+    /// Metadata for `url` without downloading it.
+    pub async fn info(&self, url: &str) -> anyhow::Result<Info> {
+        let output = self
+            .command()
+            .args(["-j", "--no-playlist", "--", url])
+            .output()
+            .await
+            .context("could not run yt-dlp")?;
+        if !output.status.success() {
+            bail!(
+                "yt-dlp failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        serde_json::from_slice(&output.stdout).context("unexpected yt-dlp output")
+    }
+
+    /// Logs the installed version, warning when it can't be run or looks
+    /// too old to keep up with site changes.
+    pub async fn check_version(&self) {
+        let output = match Command::new(&self.program).arg("--version").output().await {
+            Ok(output) if output.status.success() => output,
+            Ok(output) => {
+                warn!(
+                    "{} --version failed: {}",
+                    self.program,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    "could not run {}, only direct links will play: {e}",
+                    self.program
+                );
+                return;
+            }
+        };
+        let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        info!("Using {} {version}", self.program);
+
+        // Versions are release dates, e.g. `2024.08.06` or `2024.08.06.232958`.
+        let released = version
+            .get(..10)
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y.%m.%d").ok());
+        if let Some(released) = released {
+            let age = (Utc::now().date_naive() - released).num_days();
+            if age > OUTDATED_DAYS {
+                warn!(
+                    "{} {version} is {age} days old, consider updating",
+                    self.program
+                );
+            }
+        }
+    }
+}